@@ -0,0 +1,17 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// The cli embeds the starter food database, built by make_starter_db.py or `cli import-myfooddata`.  It isn't checked
+// in, so embed an empty blob when it's missing and let the cli start with no foods.
+const STARTER_DB_PATH: &str = "src/assets/default.fdb.bz2";
+
+fn main() {
+	println!("cargo:rerun-if-changed={}", STARTER_DB_PATH);
+	let embedded = Path::new(&env::var("OUT_DIR").expect("Cargo sets OUT_DIR for build scripts.")).join("default.fdb.bz2");
+	if Path::new(STARTER_DB_PATH).exists() {
+		fs::copy(STARTER_DB_PATH, &embedded).expect("Failed to copy the starter DB.");
+	} else {
+		fs::write(&embedded, b"").expect("Failed to write an empty starter DB.");
+	}
+}
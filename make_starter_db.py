@@ -1,6 +1,61 @@
+import bz2
 import csv
 import json
 
+# Optional Nutrients fields and the spreadsheet columns they come from.  Units already match.
+OPTIONAL_NUTRIENT_COLUMNS = {
+	"fiber": "Fiber (g)",
+	"sugars": "Sugars (g)",
+	"added_sugars": "Added Sugar (g)",
+	"saturated_fats": "Saturated Fats (g)",
+	"trans_fats": "Trans Fatty Acids (g)",
+	"cholesterol": "Cholesterol (mg)",
+	"sodium": "Sodium (mg)",
+	"potassium": "Potassium, K (mg)",
+	"calcium": "Calcium (mg)",
+	"iron": "Iron, Fe (mg)",
+	"magnesium": "Magnesium (mg)",
+	"phosphorus": "Phosphorus, P (mg)",
+	"zinc": "Zinc, Zn (mg)",
+	"vitamin_a": "Vitamin A, RAE (mcg)",
+	"vitamin_c": "Vitamin C (mg)",
+	"vitamin_d": "Vitamin D (mcg)",
+	"vitamin_e": "Vitamin E (Alpha-Tocopherol) (mg)",
+	"vitamin_k": "Vitamin K (mcg)",
+	"thiamin": "Thiamin (B1) (mg)",
+	"riboflavin": "Riboflavin (B2) (mg)",
+	"niacin": "Niacin (B3) (mg)",
+	"vitamin_b6": "Vitamin B6 (mg)",
+	"folate": "Folate DFE (mcg)",
+	"vitamin_b12": "Vitamin B-12 (mcg)",
+}
+
+def optional_nutrients(row:dict) -> dict:
+	# Blank cells are unknown, not zero, so leave them out.
+	found = dict()
+	for field, column in OPTIONAL_NUTRIENT_COLUMNS.items():
+		value = (row.get(column) or "").strip()
+		if value:
+			found[field] = float(value)
+	return found
+
+def portions(row:dict) -> list:
+	# MyFoodData has up to nine servings, described like "1 cup, chopped" with a weight for that many.
+	found = []
+	for idx in range(1, 10):
+		weight = (row.get(f'Serving Weight {idx} (g)') or "").strip()
+		description = (row.get(f'Serving Description {idx} (g)') or "").strip()
+		if not weight or not description:
+			continue
+		count, _, name = description.partition(' ')
+		try:
+			count = float(count)
+		except ValueError:
+			count, name = 1.0, description
+		if count > 0 and name and float(weight) > 0:
+			found.append({"name": name, "grams": float(weight) / count})
+	return found
+
 def main(empty_db_filename:str, food_nutrition_csv_filename:str, output_db_filename:str):
 	with open(food_nutrition_csv_filename, 'rt', encoding='utf-8-sig') as fin:
 		# All of these are given in units per 100 grams.
@@ -15,23 +70,32 @@ def main(empty_db_filename:str, food_nutrition_csv_filename:str, output_db_filen
 			"name": nd['Name'],
 			"manufacturer": "",
             "nutrition":{
-                "calories": int(float(nd['Calories'])),
+                "calories": float(nd['Calories']),
                 "carbohydrates": float(nd['Carbohydrate (g)']),
                 "proteins": float(nd['Protein (g)']),
                 "fats": float(nd['Fat (g)']),
+                **optional_nutrients(nd),
             },
 			"mass": 100,
-			"volume_of_100g": 1,
+			"volume_of_100g": 1, # Placeholder.  FoodDB::estimate_missing_densities fills it in from the food's category.
+			"volume_estimated": False,
 			"servings_in_100g": 100/max(1.0, float(nd.get('Serving Weight 2 (g)', "100") or "100")),
 			"user_defined":False,
 			"ingredients":[],
+			"portions": portions(nd),
+			"cooked_mass": None,
 			"tags":"",
 		} for nd in nutrition_data]
 		# {"foods":[{"parent_id":0,"id":0,"name":"Tasty Food","manufacturer":"","tags":"","nutrition":{"calories":260,"fats":20.0,"carbohydrates":60.0,"proteins":20.0},"mass":100,"volume_of_100g":0.0,"servings_in_100g":0.0,"user_defined":true,"ingredients":[]}],"meals":[]}
 		base_db['meals'] = []
-	with open(output_db_filename, 'wt') as fout:
+		base_db['food_ids'] = {"next_id": 1 + max([f['id'] for f in base_db['foods']], default=-1)}
+		base_db['meal_ids'] = {"next_id": 0}
+		# Keep in sync with CURRENT_SCHEMA_VERSION in src/migration.rs when the shape written above changes.
+		base_db['schema_version'] = 9
+	opener = bz2.open if output_db_filename.endswith('.bz2') else open
+	with opener(output_db_filename, 'wt') as fout:
 		json.dump(base_db, fout)
 
 if __name__=="__main__":
-	main("empty.fdb", "MyFoodData_Nutrition_Facts_SpreadSheet_Release_1_4.csv", "default.fdb")
+	main("empty.fdb", "MyFoodData_Nutrition_Facts_SpreadSheet_Release_1_4.csv", "default.fdb.bz2")
     
\ No newline at end of file
@@ -0,0 +1,159 @@
+use crate::error::{Error, Result};
+
+// Every retail barcode we accept is a GTIN, stored as the 13 digit EAN-13 form.
+const GTIN_13_LENGTH: usize = 13;
+
+/// Check a scanned or typed barcode and convert it to the 13 digit form used for storage and lookup.
+/// Accepts EAN-13, UPC-A (12 digits), UPC-E (8 digits, starting with 0 or 1), EAN-8 and GTIN-14 with a leading
+/// zero.  Spaces and dashes are ignored.  EAN-8 codes are zero-padded, which keeps their check digit valid.
+///
+/// Many 8 digit codes starting with 0 or 1 are valid both as UPC-E and as EAN-8, for two unrelated products.  They
+/// are read as UPC-E, which is what small US and Canadian packages carry, and as EAN-8 only when the UPC-E check digit
+/// is wrong.  Use normalize_ean_8 or normalize_upc_e when the scanner reported the symbology.
+pub fn normalize_barcode(code: &str) -> Result<String> {
+	let digits = barcode_digits(code)?;
+	let gtin = match digits.len() {
+		8 => match expand_upc_e(&digits) {
+			Some(upc_a) if has_valid_check_digit(&upc_a) => upc_a,
+			_ => digits,
+		},
+		12 | 13 => digits,
+		14 if digits.starts_with('0') => digits[1..].to_string(),
+		n => { return Err(Error::Validation(format!("barcode '{}' has {} digits, not 8, 12, 13 or 14", code, n))); },
+	};
+	to_gtin_13(code, gtin)
+}
+
+/// Like normalize_barcode, for a code known to be EAN-8 rather than UPC-E.
+pub fn normalize_ean_8(code: &str) -> Result<String> {
+	let digits = barcode_digits(code)?;
+	if digits.len() != 8 {
+		return Err(Error::Validation(format!("barcode '{}' isn't EAN-8, which has 8 digits", code)));
+	}
+	to_gtin_13(code, digits)
+}
+
+/// Like normalize_barcode, for a code known to be UPC-E rather than EAN-8.
+pub fn normalize_upc_e(code: &str) -> Result<String> {
+	let digits = barcode_digits(code)?;
+	let upc_a = expand_upc_e(&digits).ok_or_else(|| Error::Validation(format!("barcode '{}' isn't UPC-E, which has 8 digits starting with 0 or 1", code)))?;
+	to_gtin_13(code, upc_a)
+}
+
+// Every valid reading of a code.  Only 8 digit codes can have more than one, the first being normalize_barcode's.
+pub(crate) fn barcode_readings(code: &str) -> Result<Vec<String>> {
+	let mut readings = vec![normalize_barcode(code)?];
+	if let Ok(ean_8) = normalize_ean_8(code) {
+		if !readings.contains(&ean_8) {
+			readings.push(ean_8);
+		}
+	}
+	Ok(readings)
+}
+
+fn barcode_digits(code: &str) -> Result<String> {
+	let digits: String = code.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+	if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+		return Err(Error::Validation(format!("barcode '{}' should only contain digits", code)));
+	}
+	Ok(digits)
+}
+
+fn to_gtin_13(code: &str, gtin: String) -> Result<String> {
+	if !has_valid_check_digit(&gtin) {
+		return Err(Error::Validation(format!("barcode '{}' has the wrong check digit; was it mistyped?", code)));
+	}
+	Ok(format!("{:0>width$}", gtin, width = GTIN_13_LENGTH))
+}
+
+// GTIN check digits weight the other digits 3, 1, 3, ... from the right.
+fn has_valid_check_digit(digits: &str) -> bool {
+	let values: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+	let (check, body) = match values.split_last() {
+		Some(split) => split,
+		None => { return false; },
+	};
+	let sum: u32 = body.iter().rev().enumerate().map(|(idx, d)| if idx % 2 == 0 { d * 3 } else { *d }).sum();
+	(10 - sum % 10) % 10 == *check
+}
+
+// UPC-E squeezes the zeros out of a UPC-A code.  Its last data digit says where they were.
+fn expand_upc_e(code: &str) -> Option<String> {
+	let d: Vec<char> = code.chars().collect();
+	if d.len() != 8 || !(d[0] == '0' || d[0] == '1') {
+		return None;
+	}
+	let (number_system, data, check) = (d[0], &d[1..7], d[7]);
+	let body: String = match data[5] {
+		'0' | '1' | '2' => [data[0], data[1], data[5], '0', '0', '0', '0', data[2], data[3], data[4]].iter().collect(),
+		'3' => [data[0], data[1], data[2], '0', '0', '0', '0', '0', data[3], data[4]].iter().collect(),
+		'4' => [data[0], data[1], data[2], data[3], '0', '0', '0', '0', '0', data[4]].iter().collect(),
+		_ => [data[0], data[1], data[2], data[3], data[4], '0', '0', '0', '0', data[5]].iter().collect(),
+	};
+	Some(format!("{}{}{}", number_system, body, check))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+
+	#[test]
+	fn barcode_formats() {
+		// The same can of soda in every format.
+		assert_eq!(normalize_barcode("0 42100 00526 4").unwrap(), "0042100005264");
+		assert_eq!(normalize_barcode("04252614").unwrap(), "0042100005264"); // UPC-E.
+		assert_eq!(normalize_barcode("0042100005264").unwrap(), "0042100005264");
+		assert_eq!(normalize_barcode("00042100005264").unwrap(), "0042100005264"); // GTIN-14.
+
+		assert_eq!(normalize_barcode("3017620422003").unwrap(), "3017620422003");
+		assert_eq!(normalize_barcode("9638-5074").unwrap(), "0000096385074"); // EAN-8.
+
+		// Valid as UPC-E and as EAN-8.  It's taken as UPC-E unless the caller knows better.
+		assert_eq!(normalize_barcode("12345670").unwrap(), "0123456000070");
+		assert_eq!(normalize_upc_e("12345670").unwrap(), "0123456000070");
+		assert_eq!(normalize_ean_8("12345670").unwrap(), "0000012345670");
+		assert_eq!(normalize_ean_8("9638-5074").unwrap(), "0000096385074");
+		assert!(matches!(normalize_ean_8("04252614"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_ean_8("0042100005264"), Err(Error::Validation(_))));
+		assert_eq!(normalize_upc_e("04252614").unwrap(), "0042100005264");
+		assert!(matches!(normalize_upc_e("96385074"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_upc_e("04252615"), Err(Error::Validation(_))));
+
+		assert!(matches!(normalize_barcode("3017620422004"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_barcode("301762042200"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_barcode("12345"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_barcode("30176204220O3"), Err(Error::Validation(_))));
+		assert!(matches!(normalize_barcode(""), Err(Error::Validation(_))));
+	}
+
+	#[test]
+	fn find_foods_by_barcode() {
+		let mut db = FoodDB::new();
+		let soda = db.new_food().id;
+		let store_brand = db.new_food().id;
+		db.add_barcode(soda, "042100005264").unwrap();
+		db.add_barcode(soda, "04252614").unwrap(); // Same code, scanned off a small can.
+		assert_eq!(db.food(soda).unwrap().barcodes, vec!["0042100005264".to_string()]);
+		assert!(matches!(db.add_barcode(store_brand, "042100005265"), Err(Error::Validation(_))));
+		assert!(matches!(db.add_barcode(store_brand + 1, "96385074"), Err(Error::UnknownFood(_))));
+
+		assert_eq!(db.find_by_barcode("0 42100 00526 4").unwrap(), vec![soda]);
+		assert!(db.find_by_barcode("96385074").unwrap().is_empty());
+		assert!(db.find_by_barcode("4210000526").is_err());
+
+		// Codes saved before they were validated still work, as long as they're real codes.
+		db.food_mut(store_brand).unwrap().barcodes = vec!["96385074".to_string(), "junk".to_string()];
+		db.reindex();
+		assert_eq!(db.find_by_barcode("0000096385074").unwrap(), vec![store_brand]);
+
+		// An ambiguous code finds foods under either reading, the usual one first.
+		let chocolate = db.new_food().id;
+		let gum = db.new_food().id;
+		db.add_barcode(gum, &normalize_ean_8("12345670").unwrap()).unwrap();
+		db.add_barcode(chocolate, "12345670").unwrap();
+		assert_eq!(db.food(chocolate).unwrap().barcodes, vec!["0123456000070".to_string()]);
+		assert_eq!(db.find_by_barcode("12345670").unwrap(), vec![chocolate, gum]);
+		assert_eq!(db.find_by_barcode(&normalize_upc_e("12345670").unwrap()).unwrap(), vec![chocolate]);
+	}
+}
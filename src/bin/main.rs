@@ -26,7 +26,7 @@ async fn main() {
 		fid.manufacturer = "TestManufacturer".to_string();
 		fid.volume_of_100g = 100.0;
 		fid.servings_in_100g = 1.0;
-		fid.nutrition.calories = 0;
+		fid.nutrition.calories = 0.0;
 		fid.nutrition.carbohydrates = 0.0;
 		fid.nutrition.fats = 0.0;
 		fid.nutrition.proteins = 0.0;
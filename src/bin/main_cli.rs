@@ -1,22 +1,36 @@
-use fooddb::{FoodDB, FoodID, FoodQuantity};
+use chrono::NaiveDate;
+use fooddb::{normalize_ean_8, normalize_upc_e, Compression, FoodDB, FoodID, SearchContext};
+use fooddb::import::{myfooddata, openfoodfacts, usda};
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use hashbrown::HashMap;
 use std::iter::FromIterator;
-use std::array::IntoIter;
-use std::convert::TryFrom;
+
+// Built into the binary by build.rs.  Empty if there was no starter DB at build time.
+const STARTER_DB: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/default.fdb.bz2"));
+// Where the importers write by default, relative to the repository root.  Rebuild afterwards to embed the new foods.
+const STARTER_DB_PATH: &str = "src/assets/default.fdb.bz2";
 
 // Structure across all app modes.
 struct AppState {
+	#[allow(dead_code)]
 	result_stack: Vec<String>,
 	food_db: FoodDB,
 	quit: bool,
 }
 
 fn main() {
-	//let mut food_db = FoodDB::from_string(mq::file::load_string(""));
-	let mut food_db = FoodDB::from_string(include_str!("../assets/default.fdb")).unwrap();
-	food_db.reindex();
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() > 1 {
+		if let Err(e) = run_subcommand(&args[1..]) {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	let food_db = load_starter_db();
 
 	let mut app_state = AppState {
 		result_stack: vec![],
@@ -29,11 +43,163 @@ fn main() {
 	}
 }
 
+fn run_subcommand(args: &[String]) -> Result<(), String> {
+	match args {
+		[command, csv_path, rest @ ..] if command == "import-myfooddata" && rest.len() <= 1 => {
+			let output = rest.first().map(|s| s.as_str()).unwrap_or(STARTER_DB_PATH);
+			import_myfooddata(csv_path, output).map_err(|e| format!("Failed to import {}: {}", csv_path, e))
+		},
+		[command, source, rest @ ..] if command == "import-usda" && rest.len() <= 1 => {
+			let database = rest.first().map(|s| s.as_str()).unwrap_or(STARTER_DB_PATH);
+			import_usda(source, database).map_err(|e| format!("Failed to import {}: {}", source, e))
+		},
+		[command, source, rest @ ..] if command == "import-off" => {
+			let (filter, database) = parse_off_options(rest)?;
+			let database = database.unwrap_or(STARTER_DB_PATH);
+			import_off(source, database, filter).map_err(|e| format!("Failed to import {}: {}", source, e))
+		},
+		[command, database, from, to, rest @ ..] if command == "export-diary" && rest.len() <= 1 => {
+			let daily = match rest.first().map(|s| s.as_str()) {
+				None => false,
+				Some("--daily") => true,
+				Some(other) => { return Err(format!("Unexpected argument '{}'", other)); },
+			};
+			export_diary(database, from, to, daily).map_err(|e| format!("Failed to export {}: {}", database, e))
+		},
+		[command, database, query] if command == "query" => {
+			query_foods(database, query).map_err(|e| format!("Failed to query {}: {}", database, e))
+		},
+		_ => Err("Usage: cli [import-myfooddata <csv> [output.fdb or output.fdb.bz2]]\n       cli [import-usda <download.json or csv directory> [database.fdb or database.fdb.bz2]]\n       cli [import-off <dump.jsonl, dump.csv or - for JSONL on stdin> [database.fdb or database.fdb.bz2] [--country <name>]... [--min-completeness <0 to 1>]]\n       cli [export-diary <database.fdb or database.fdb.bz2> <first day YYYY-MM-DD> <last day YYYY-MM-DD> [--daily]]\n       cli [query <database.fdb or database.fdb.bz2> \"protein per 100 kcal > 10, fat < 5 g, tag:vegetarian, sorted by protein density\"]".to_string()),
+	}
+}
+
+// Merges into `output` if it exists, so importing a newer release of the spreadsheet updates the foods from the last one.
+fn import_myfooddata(csv_path: &str, output: &str) -> fooddb::Result<()> {
+	let foods = myfooddata::read_foods(File::open(csv_path)?)?;
+	let mut db = open_or_new(output)?;
+	let summary = db.import_foods(foods);
+	let estimated = db.estimate_missing_densities();
+	save(&db, output)?;
+	println!("Added {} and updated {} foods ({} with estimated densities) in {}.", summary.added, summary.updated, estimated, output);
+	Ok(())
+}
+
+// Merges into `database` if it exists, so importing a newer release updates the foods from the last one.
+fn import_usda(source: &str, database: &str) -> fooddb::Result<()> {
+	let foods = if std::path::Path::new(source).is_dir() {
+		usda::read_csv_dir(source)?
+	} else {
+		usda::read_json(File::open(source)?)?
+	};
+	let mut db = open_or_new(database)?;
+	let summary = db.import_foods(foods);
+	let estimated = db.estimate_missing_densities();
+	save(&db, database)?;
+	println!("Added {} and updated {} foods ({} with estimated densities) in {}.", summary.added, summary.updated, estimated, database);
+	Ok(())
+}
+
+fn parse_off_options(args: &[String]) -> Result<(openfoodfacts::Filter, Option<&str>), String> {
+	let mut filter = openfoodfacts::Filter::default();
+	let mut database = None;
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--country" => filter.countries.push(args.next().ok_or("--country needs a country name")?.clone()),
+			"--min-completeness" => {
+				let value = args.next().ok_or("--min-completeness needs a number")?;
+				filter.min_completeness = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+			},
+			path if database.is_none() && !path.starts_with("--") => { database = Some(path); },
+			other => { return Err(format!("Unexpected argument '{}'", other)); },
+		}
+	}
+	Ok((filter, database))
+}
+
+// Only the products that pass the filter are held in memory.  The dump itself is read a line at a time.
+fn import_off(source: &str, database: &str, filter: openfoodfacts::Filter) -> fooddb::Result<()> {
+	let foods = if source == "-" {
+		openfoodfacts::read_jsonl(io::stdin().lock(), filter).collect::<fooddb::Result<Vec<_>>>()?
+	} else if source.ends_with(".csv") {
+		openfoodfacts::read_csv(File::open(source)?, filter)?.collect::<fooddb::Result<Vec<_>>>()?
+	} else {
+		openfoodfacts::read_jsonl(io::BufReader::new(File::open(source)?), filter).collect::<fooddb::Result<Vec<_>>>()?
+	};
+	let mut db = open_or_new(database)?;
+	let summary = db.import_foods(foods);
+	let estimated = db.estimate_missing_densities();
+	save(&db, database)?;
+	println!("Added {} and updated {} foods ({} with estimated densities) in {}.", summary.added, summary.updated, estimated, database);
+	Ok(())
+}
+
+// CSV goes to stdout so it can be redirected into a file or piped into another tool.
+fn export_diary(database: &str, from: &str, to: &str, daily: bool) -> fooddb::Result<()> {
+	let parse_day = |day: &str| NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|e| fooddb::Error::Validation(format!("'{}' is not a YYYY-MM-DD date: {}", day, e)));
+	let days = parse_day(from)?..=parse_day(to)?;
+	let db = FoodDB::open_any(database)?;
+	let stdout = io::stdout();
+	if daily {
+		db.export_daily_totals_csv(days, stdout.lock())?;
+	} else {
+		db.export_diary_csv(days, stdout.lock())?;
+	}
+	Ok(())
+}
+
+fn query_foods(database: &str, query: &str) -> fooddb::Result<()> {
+	let db = FoodDB::open_any(database)?;
+	for food_id in db.query_foods_str(query)? {
+		let food = db.food(food_id)?;
+		println!("{}: {} {}", food.id, food.name, food.manufacturer);
+	}
+	Ok(())
+}
+
+fn open_or_new(database: &str) -> fooddb::Result<FoodDB> {
+	match FoodDB::open_any(database) {
+		Ok(db) => Ok(db),
+		Err(fooddb::Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => Ok(FoodDB::new()),
+		Err(e) => Err(e),
+	}
+}
+
+fn save(db: &FoodDB, filename: &str) -> fooddb::Result<()> {
+	// The default starter DB location may not exist yet in a fresh checkout.
+	if let Some(dir) = std::path::Path::new(filename).parent() {
+		std::fs::create_dir_all(dir)?;
+	}
+	if filename.ends_with(".bz2") {
+		db.save_compressed(filename, Compression::best())
+	} else {
+		db.save(filename)
+	}
+}
+
+fn load_starter_db() -> FoodDB {
+	if STARTER_DB.is_empty() {
+		eprintln!("No starter DB was built in.  Starting with an empty food database.");
+		return FoodDB::new();
+	}
+	match FoodDB::from_bytes(STARTER_DB) {
+		Ok(mut db) => {
+			db.estimate_missing_densities();
+			db
+		},
+		Err(e) => {
+			eprintln!("Failed to load the built-in starter DB: {}", e);
+			FoodDB::new()
+		},
+	}
+}
+
 fn main_menu(app_state: &mut AppState) {
 	// Until we have hashmap macros...
-	let main_menu_options = HashMap::<char, &str>::from_iter(IntoIter::new([
+	let main_menu_options = HashMap::<char, &str>::from_iter(IntoIterator::into_iter([
 		('n', "New Food"),
 		('s', "Search Food"),
+		('b', "Find Food by Barcode"),
 		('q', "Quit"),
 	]));
 	match show_map_menu(
@@ -43,6 +209,7 @@ fn main_menu(app_state: &mut AppState) {
 	) {
 		'q' => { app_state.quit = true; },
 		's' => { search_food_menu(app_state); },
+		'b' => { barcode_menu(app_state); },
 		_ => {}
 	};
 }
@@ -57,18 +224,52 @@ fn search_food_menu(app_state: &AppState) -> FoodID {
 		if bytes_read == 0 {
 			panic!("Failed to read from STDIN.");
 		}
-		let matches:Vec<(FoodID, String)> = app_state.food_db.get_autocomplete_suggestions(buffer.trim().to_string());
-		let (food_ids, food_names): (Vec<FoodID>, Vec<String>) = matches.iter().map(|(fid, fname)|{ (fid, fname.clone()) }).unzip();
+		// Foods usually logged around now come first.
+		let context = SearchContext { boost_time_of_day: true, ..SearchContext::default() };
+		let matches = app_state.food_db.search_foods_in_context(buffer.trim(), None, &context);
+		let (food_ids, food_names): (Vec<FoodID>, Vec<String>) = matches.iter().map(|m|{ (m.id, format!("{} ({:.0}%)", m.name, 100.0 * m.relevance)) }).unzip();
 		drop(stdin);
-		drop(stdin_lock);
-		return food_ids[show_list_menu("Best Matches:", food_names) as usize];
+		food_ids[show_list_menu("Best Matches:", food_names) as usize]
 	} else {
 		panic!("Unable to read from STDIN.");
 	}
 }
 
+// Phone scanner apps copy the digits, sometimes with spaces.  Paste them here.  Most also show the symbology, which
+// settles 8 digit codes that could be UPC-E or EAN-8.
+fn barcode_menu(app_state: &AppState) {
+	let mut buffer = String::new();
+	println!("Enter or paste a barcode: ");
+	if io::stdin().read_line(&mut buffer).unwrap_or(0) == 0 {
+		panic!("Failed to read from STDIN.");
+	}
+	let mut symbology = String::new();
+	println!("Barcode type if the scanner showed it (UPC-E, EAN-8), or Enter to try every type: ");
+	if io::stdin().read_line(&mut symbology).unwrap_or(0) == 0 {
+		panic!("Failed to read from STDIN.");
+	}
+	let code = buffer.trim();
+	// find_by_barcode takes the 13 digit form as it is.
+	let found = match symbology.trim().to_lowercase().replace('-', "").as_str() {
+		"" => app_state.food_db.find_by_barcode(code),
+		"upce" => normalize_upc_e(code).and_then(|code| app_state.food_db.find_by_barcode(&code)),
+		"ean8" => normalize_ean_8(code).and_then(|code| app_state.food_db.find_by_barcode(&code)),
+		other => Err(fooddb::Error::Validation(format!("unknown barcode type '{}'; expected UPC-E or EAN-8", other))),
+	};
+	match found {
+		Ok(food_ids) if food_ids.is_empty() => { println!("No food has that barcode."); },
+		Ok(food_ids) => {
+			for food in food_ids.iter().filter_map(|id| app_state.food_db.get_food_from_id(*id)) {
+				println!("{}: {} {}", food.id, food.name, food.manufacturer);
+			}
+		},
+		Err(e) => { println!("{}", e); },
+	}
+}
+
+#[allow(dead_code)]
 fn confirm(prompt: &str) -> bool {
-	let options = HashMap::<char, &str>::from_iter(IntoIter::new([('y', "Yes"), ('n', "No")]));
+	let options = HashMap::<char, &str>::from_iter(IntoIterator::into_iter([('y', "Yes"), ('n', "No")]));
 	show_map_menu(prompt, options, None) == 'y'
 }
 
@@ -84,7 +285,7 @@ fn show_list_menu(prompt: &str, options: Vec<String>) -> u32 {
 			println!("{}: {}", ch+1, desc);
 		}
 
-		if let Ok(bytes_read) = stdin.read_line(&mut buffer) {
+		if stdin.read_line(&mut buffer).is_ok() {
 			if let Ok(num) = buffer.parse::<u32>() {
 				if num >= 1 && num <= options.len() as u32 {
 					choice = num;
@@ -120,10 +321,10 @@ fn show_map_menu(prompt: &str, options: HashMap<char, &str>, default:Option<char
 			if bytes_read == 0 {
 				// END OF STREAM!
 				// Failure.
-			} else if buffer.len() == 0 {
+			} else if buffer.is_empty() {
 
 			} else {
-				let user_input = buffer.chars().nth(0).expect("Buffer len greater than zero must be true.");
+				let user_input = buffer.chars().next().expect("Buffer len greater than zero must be true.");
 				// Did user hit the default?
 				if user_input == '\n' && default.is_some() {
 					choice = default;
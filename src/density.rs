@@ -0,0 +1,149 @@
+use crate::food::Food;
+use crate::FoodDB;
+
+/// Broad groups of foods with similar densities, for guessing `volume_of_100g` when a food doesn't say.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DensityCategory {
+	Oil,
+	Liquid,
+	Flour,
+	Grain,
+	LeafyGreen,
+}
+
+// Grams per ml, and the words that put a food in the category.  Checked in order, so "rice flour" is a flour and
+// "olive oil" isn't just a liquid.  Rough kitchen averages; a measured value always wins.
+const CATEGORY_DENSITIES: [(DensityCategory, f32, &[&str]); 5] = [
+	(DensityCategory::Oil, 0.92, &["oil", "oils", "ghee", "shortening"]),
+	(DensityCategory::Liquid, 1.03, &["liquid", "liquids", "beverage", "beverages", "drink", "water", "milk", "juice", "broth", "stock", "soda", "coffee", "tea", "wine", "beer", "vinegar", "kefir", "buttermilk", "cream"]),
+	(DensityCategory::Flour, 0.53, &["flour", "flours", "cornmeal", "starch", "semolina"]),
+	(DensityCategory::Grain, 0.78, &["grain", "grains", "rice", "oats", "oat", "quinoa", "barley", "couscous", "bulgur", "millet", "buckwheat", "farro", "cereal", "cereals"]),
+	(DensityCategory::LeafyGreen, 0.15, &["leafy", "greens", "lettuce", "spinach", "kale", "arugula", "chard", "collards", "cabbage", "watercress", "endive"]),
+];
+
+// The starter DB importer writes 1 ml per 100g when it doesn't know.  No real food is 100 g/ml.
+const PLACEHOLDER_VOLUME_OF_100G: f32 = 1.0;
+
+impl DensityCategory {
+	pub fn grams_per_ml(self) -> f32 {
+		CATEGORY_DENSITIES.iter().find(|(category, _, _)| *category == self).map(|(_, density, _)| *density).expect("Every density category has a density.")
+	}
+
+	pub fn volume_of_100g(self) -> f32 {
+		100.0 / self.grams_per_ml()
+	}
+
+	pub fn all() -> impl Iterator<Item=DensityCategory> {
+		CATEGORY_DENSITIES.iter().map(|(category, _, _)| *category)
+	}
+
+	/// Guess a food's category.  Tags are trusted first.  Failing that, only the head of the name counts, since
+	/// "Milk, whole" is milk but "Chocolate, milk" is not.
+	pub fn of_food(food: &Food) -> Option<DensityCategory> {
+		let tags: Vec<String> = food.tags.split('|').map(|t| t.trim().to_lowercase()).collect();
+		let head = food.name.split(',').next().unwrap_or("").to_lowercase();
+		let name_words: Vec<&str> = head.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+
+		let matching = |words: &[&str]| CATEGORY_DENSITIES.iter()
+			.find(|(_, _, keywords)| keywords.iter().any(|k| words.contains(k)))
+			.map(|(category, _, _)| *category);
+		let tag_words: Vec<&str> = tags.iter().map(|t| t.as_str()).collect();
+		matching(&tag_words).or_else(|| matching(&name_words))
+	}
+}
+
+impl Food {
+	/// Does this food have a usable density of its own (measured or already estimated)?  Only zero and the importer's
+	/// placeholder count as missing; `volume_estimated` tells estimates apart.
+	pub fn has_density(&self) -> bool {
+		self.volume_of_100g > 0.0 && self.volume_of_100g != PLACEHOLDER_VOLUME_OF_100G
+	}
+
+	/// Record a measured density, replacing any estimate.
+	pub fn set_volume_of_100g(&mut self, volume_of_100g: f32) {
+		self.volume_of_100g = volume_of_100g;
+		self.volume_estimated = false;
+	}
+}
+
+impl FoodDB {
+	/// Fill in `volume_of_100g` from the food's category wherever it's missing or a placeholder, marking each one
+	/// with `volume_estimated`.  Earlier estimates are refreshed.  Returns how many foods got an estimate.
+	pub fn estimate_missing_densities(&mut self) -> usize {
+		let mut estimated = 0;
+		for food in self.foods.iter_mut().filter(|f| f.volume_estimated || !f.has_density()) {
+			if let Some(category) = DensityCategory::of_food(food) {
+				food.volume_of_100g = category.volume_of_100g();
+				food.volume_estimated = true;
+				estimated += 1;
+			}
+		}
+		estimated
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+
+	// The category table should stay within 0.05 to 3 g/ml.  Measured foods can be anything.
+	const PLAUSIBLE_VOLUME_OF_100G: std::ops::RangeInclusive<f32> = 33.0..=2000.0;
+
+	#[test]
+	fn categories_from_tags_and_names() {
+		let named = |name: &str, tags: &str| DensityCategory::of_food(&Food { name: name.to_string(), tags: tags.to_string(), ..Food::default() });
+		assert_eq!(named("Milk, whole", ""), Some(DensityCategory::Liquid));
+		assert_eq!(named("Oil, olive, extra virgin", ""), Some(DensityCategory::Oil));
+		assert_eq!(named("Rice flour, white", ""), Some(DensityCategory::Flour));
+		assert_eq!(named("Oats, rolled", ""), Some(DensityCategory::Grain));
+		assert_eq!(named("Spinach, raw", ""), Some(DensityCategory::LeafyGreen));
+		assert_eq!(named("Chocolate, milk", ""), None);
+		assert_eq!(named("House dressing", "Oils|Condiments"), Some(DensityCategory::Oil));
+		assert!(DensityCategory::all().all(|c| PLAUSIBLE_VOLUME_OF_100G.contains(&c.volume_of_100g())));
+	}
+
+	#[test]
+	fn estimates_only_fill_gaps() {
+		let mut db = FoodDB::new();
+		let oats = {
+			let f = db.new_food();
+			f.name = "Oats, rolled".to_string();
+			f.volume_of_100g = 1.0; // Importer placeholder.
+			f.id
+		};
+		let milk = {
+			let f = db.new_food();
+			f.name = "Milk, whole".to_string();
+			f.set_volume_of_100g(97.0);
+			f.id
+		};
+		let mystery = db.new_food().id;
+
+		assert_eq!(db.estimate_missing_densities(), 1);
+		let oats = db.food(oats).unwrap();
+		assert!(oats.volume_estimated);
+		assert!((oats.volume_of_100g - DensityCategory::Grain.volume_of_100g()).abs() < 1e-4);
+		assert!(!db.food(milk).unwrap().volume_estimated);
+		assert_eq!(db.food(milk).unwrap().volume_of_100g, 97.0);
+		assert!(!db.food(mystery).unwrap().has_density());
+
+		// Measuring replaces the estimate, and it stays put.
+		db.food_mut(0).unwrap().set_volume_of_100g(250.0);
+		assert_eq!(db.estimate_missing_densities(), 0);
+		assert_eq!(db.food(0).unwrap().volume_of_100g, 250.0);
+
+		// Popcorn is about 0.03 g/ml.  Light, but measured, so it's kept and can be logged by volume.
+		let popcorn = {
+			let f = db.new_food();
+			f.name = "Popcorn, air-popped".to_string();
+			f.tags = "Grains".to_string();
+			f.set_volume_of_100g(3300.0);
+			f.id
+		};
+		assert_eq!(db.estimate_missing_densities(), 0);
+		assert_eq!(db.food(popcorn).unwrap().volume_of_100g, 3300.0);
+		let hundreds_of_grams = db.food(popcorn).unwrap().try_scale_factor(&FoodQuantity::Volume(330.0)).unwrap();
+		assert!((hundreds_of_grams - 0.1).abs() < 1e-6);
+	}
+}
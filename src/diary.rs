@@ -0,0 +1,148 @@
+// Spreadsheet-friendly exports of the meal log.  Days are calendar days in UTC, like get_meals_from_date.
+
+use std::io::Write;
+use std::ops::RangeBounds;
+
+use chrono::{NaiveDate, SecondsFormat};
+
+use crate::error::Result;
+use crate::meal::Meal;
+use crate::nutrition::{Nutrients, OPTIONAL_NUTRIENTS};
+use crate::FoodDB;
+
+impl FoodDB {
+	/// Write one CSV row per food logged in a meal on the given days, oldest first, with the nutrition of the amount
+	/// eaten.  Unknown nutrients are left blank.  Returns the number of rows written, not counting the header.
+	pub fn export_diary_csv<R: RangeBounds<NaiveDate>, W: Write>(&self, range: R, writer: W) -> Result<usize> {
+		let mut csv_writer = csv::Writer::from_writer(writer);
+		csv_writer.write_record(["time", "meal", "food", "quantity", "unit"].iter().map(|s| s.to_string()).chain(nutrient_headers()))?;
+		let mut rows = 0;
+		for meal in self.meals_between(&range) {
+			let time = meal.time.to_rfc3339_opts(SecondsFormat::Secs, true);
+			for (food_id, quantity) in &meal.foods {
+				let nutrition = self.resolve_nutrition(*food_id, quantity.clone())?;
+				let food = self.food(*food_id)?;
+				let fields = vec![time.clone(), meal.meal_name.clone(), food.name.clone(), quantity.amount().to_string(), quantity.unit_name().to_string()];
+				csv_writer.write_record(fields.into_iter().chain(nutrient_fields(&nutrition)))?;
+				rows += 1;
+			}
+		}
+		csv_writer.flush()?;
+		Ok(rows)
+	}
+
+	/// Write one CSV row per day in the range that has meals, with the day's nutrition totals.  Totals are worked out
+	/// from the foods eaten, so they always agree with export_diary_csv.  A nutrient is only totalled for a day if every
+	/// food eaten that day lists it; otherwise it is left blank.  Returns the number of days written.
+	pub fn export_daily_totals_csv<R: RangeBounds<NaiveDate>, W: Write>(&self, range: R, writer: W) -> Result<usize> {
+		let mut csv_writer = csv::Writer::from_writer(writer);
+		csv_writer.write_record(["date", "meals"].iter().map(|s| s.to_string()).chain(nutrient_headers()))?;
+		let mut days: Vec<(NaiveDate, usize, Nutrients)> = vec![];
+		for meal in self.meals_between(&range) {
+			let mut total = Nutrients::zero();
+			for (food_id, quantity) in &meal.foods {
+				total += self.resolve_nutrition(*food_id, quantity.clone())?;
+			}
+			let date = meal.time.date_naive();
+			match days.last_mut() {
+				Some((day, meals, nutrition)) if *day == date => {
+					*meals += 1;
+					*nutrition += total;
+				},
+				_ => { days.push((date, 1, total)); },
+			}
+		}
+		for (date, meals, nutrition) in &days {
+			csv_writer.write_record(vec![date.to_string(), meals.to_string()].into_iter().chain(nutrient_fields(nutrition)))?;
+		}
+		csv_writer.flush()?;
+		Ok(days.len())
+	}
+
+	// Meals are kept in the order they were created, which needn't be the order they were eaten.
+	fn meals_between<R: RangeBounds<NaiveDate>>(&self, range: &R) -> Vec<&Meal> {
+		let mut meals: Vec<&Meal> = self.meals.iter().filter(|m| range.contains(&m.time.date_naive())).collect();
+		meals.sort_by_key(|m| (m.time, m.id));
+		meals
+	}
+}
+
+fn nutrient_headers() -> impl Iterator<Item=String> {
+	let macros = ["calories (kcal)", "fats (g)", "carbohydrates (g)", "proteins (g)"].iter().map(|s| s.to_string());
+	macros.chain(OPTIONAL_NUTRIENTS.iter().map(|(name, unit)| format!("{} ({})", name, unit)))
+}
+
+fn nutrient_fields(nutrition: &Nutrients) -> impl Iterator<Item=String> {
+	let macros = vec![nutrition.calories, nutrition.fats, nutrition.carbohydrates, nutrition.proteins];
+	let optional = nutrition.optional().to_vec();
+	macros.into_iter().map(|v| v.to_string()).chain(optional.into_iter().map(|v| v.map(|v| v.to_string()).unwrap_or_default()))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use chrono::{NaiveDate, TimeZone, Utc};
+
+	#[test]
+	fn diary_rows_and_daily_totals() {
+		let mut db = FoodDB::new();
+		let oats = {
+			let food = db.new_food();
+			food.name = "Oats, rolled".to_string();
+			food.nutrition.calories = 380.0;
+			food.nutrition.proteins = 12.0;
+			food.nutrition.fiber = Some(10.0);
+			food.servings_in_100g = 2.0;
+			food.id
+		};
+		let apple = {
+			let food = db.new_food();
+			food.name = "Apple".to_string();
+			food.nutrition.calories = 52.0;
+			food.id
+		};
+
+		let day = |d: u32, h: u32| Utc.with_ymd_and_hms(2021, 6, d, h, 0, 0).unwrap();
+		for (time, meal_name, foods) in [
+			(day(2, 12), "Lunch", vec![(apple, FoodQuantity::Mass(150.0))]),
+			(day(1, 8), "Breakfast", vec![(oats, FoodQuantity::Serving(1.0)), (apple, FoodQuantity::Mass(100.0))]),
+			(day(2, 8), "Breakfast", vec![(oats, FoodQuantity::Mass(100.0))]),
+			(day(9, 8), "Breakfast", vec![(oats, FoodQuantity::Mass(40.0))]),
+		] {
+			let meal = db.new_meal();
+			db.meal_mut(meal).unwrap().time = time;
+			db.meal_mut(meal).unwrap().meal_name = meal_name.to_string();
+			for (food, quantity) in foods {
+				db.add_food_to_meal(meal, food, quantity).unwrap();
+			}
+		}
+		let week = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap()..=NaiveDate::from_ymd_opt(2021, 6, 7).unwrap();
+
+		let mut out = vec![];
+		assert_eq!(db.export_diary_csv(week.clone(), &mut out).unwrap(), 4);
+		let text = String::from_utf8(out).unwrap();
+		let lines: Vec<&str> = text.lines().collect();
+		assert!(lines[0].starts_with("time,meal,food,quantity,unit,calories (kcal),fats (g),carbohydrates (g),proteins (g),fiber (g),"));
+		assert!(lines[1].starts_with("2021-06-01T08:00:00Z,Breakfast,\"Oats, rolled\",1,serving,190,0,0,6,5,,"));
+		assert!(lines[2].starts_with("2021-06-01T08:00:00Z,Breakfast,Apple,100,g,52,"));
+		assert!(lines[4].starts_with("2021-06-02T12:00:00Z,Lunch,Apple,150,g,78,"));
+		assert_eq!(lines.len(), 5);
+		assert_eq!(lines[0].split(',').count(), 5 + 4 + OPTIONAL_NUTRIENTS.len());
+
+		let mut out = vec![];
+		assert_eq!(db.export_daily_totals_csv(week, &mut out).unwrap(), 2);
+		let text = String::from_utf8(out).unwrap();
+		let lines: Vec<&str> = text.lines().collect();
+		assert!(lines[0].starts_with("date,meals,calories (kcal),"));
+		// The apple doesn't list fiber, so neither day's fiber total is known.
+		assert!(lines[1].starts_with("2021-06-01,1,242,0,0,6,,"));
+		assert!(lines[2].starts_with("2021-06-02,2,458,0,0,12,,"));
+
+		// An unbounded range takes every day with meals.
+		let mut out = vec![];
+		assert_eq!(db.export_daily_totals_csv(.., &mut out).unwrap(), 3);
+		let text = String::from_utf8(out).unwrap();
+		assert!(text.lines().nth(3).unwrap().starts_with("2021-06-09,1,152,0,0,4.8,4,"));
+	}
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::io;
+
+use crate::food::FoodID;
+use crate::meal::MealID;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+	Io(io::Error),
+	Parse { line: usize, column: usize, message: String }, // Line and column are one-indexed, as reported by serde_json.
+	Decompression(io::Error),
+	UnknownFood(FoodID),
+	UnknownMeal(MealID),
+	IdMismatch { expected: FoodID, found: FoodID }, // The food stored at a slot doesn't carry the ID we looked up.
+	IngredientCycle(FoodID), // This recipe (eventually) contains itself.
+	MissingConversion { food: FoodID, missing: String }, // The food lacks the density, serving size, or portion needed to turn a quantity into grams.
+	Validation(String),
+	Import { line: u64, message: String }, // A row of a third-party food list we couldn't make sense of.  See the import module.
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "I/O error: {}", e),
+			Error::Parse { line, column, message } => write!(f, "Malformed food database at line {}, column {}: {}", line, column, message),
+			Error::Decompression(e) => write!(f, "Failed to decompress food database: {}", e),
+			Error::UnknownFood(id) => write!(f, "No food with ID {}", id),
+			Error::UnknownMeal(id) => write!(f, "No meal with ID {}", id),
+			Error::IdMismatch { expected, found } => write!(f, "Food ID mismatch: expected {} but found {}", expected, found),
+			Error::IngredientCycle(id) => write!(f, "Food {} is an ingredient of itself", id),
+			Error::MissingConversion { food, missing } => write!(f, "Food {} can't be measured that way: it has no {}", food, missing),
+			Error::Validation(reason) => write!(f, "Invalid value: {}", reason),
+			Error::Import { line, message } => write!(f, "Import failed at line {}: {}", line, message),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) | Error::Decompression(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Self {
+		Error::Io(e)
+	}
+}
+
+impl From<csv::Error> for Error {
+	fn from(e: csv::Error) -> Self {
+		let line = e.position().map(|p| p.line()).unwrap_or(0);
+		if !e.is_io_error() {
+			return Error::Import { line, message: e.to_string() };
+		}
+		match e.into_kind() {
+			csv::ErrorKind::Io(e) => Error::Io(e),
+			_ => unreachable!("is_io_error() guarantees an Io kind."),
+		}
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(e: serde_json::Error) -> Self {
+		if e.is_io() {
+			Error::Io(e.into())
+		} else {
+			// serde_json appends the position to its message.  We keep it in separate fields, so drop the suffix.
+			let position = format!(" at line {} column {}", e.line(), e.column());
+			let message = e.to_string();
+			Error::Parse {
+				line: e.line(),
+				column: e.column(),
+				message: message.strip_suffix(&position).unwrap_or(&message).to_string(),
+			}
+		}
+	}
+}
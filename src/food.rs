@@ -1,13 +1,351 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
 //use serde_json::Result;
 
+use crate::error::Error;
 use crate::nutrition::Nutrients;
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum FoodQuantity {
-	Mass(u32), // in grams.  Max value: approximate mass of Saturn-V rocket.
+	Mass(f32), // in grams.
 	Volume(f32), // in cm^3, a.k.a., ml.  Max value: a cube with sides of several million km.
-	Serving(f32)
+	Serving(f32),
+	Portion(String, f32), // A count of one of the food's named portions, e.g., 2 of "slice".  See Food::portions.
+
+	// Everything below is converted to one of the three above.  See canonical().
+	Kilograms(f32),
+	Ounces(f32), // Avoirdupois.
+	Pounds(f32),
+	Litres(f32),
+	Cups(f32), // US customary.
+	Tablespoons(f32), // US customary.
+	Teaspoons(f32), // US customary.
+	FluidOunces(f32), // US customary.
+}
+
+pub const GRAMS_PER_KILOGRAM: f32 = 1000.0;
+pub const GRAMS_PER_OUNCE: f32 = 28.349523;
+pub const GRAMS_PER_POUND: f32 = 453.59237;
+pub const ML_PER_LITRE: f32 = 1000.0;
+pub const ML_PER_CUP: f32 = 236.58824;
+pub const ML_PER_TABLESPOON: f32 = 14.786765;
+pub const ML_PER_TEASPOON: f32 = 4.928922;
+pub const ML_PER_FLUID_OUNCE: f32 = 29.57353;
+
+impl FoodQuantity {
+	/// The same amount expressed in grams, millilitres, or servings.  Portions depend on the food, so stay as they are.
+	pub fn canonical(&self) -> FoodQuantity {
+		match *self {
+			FoodQuantity::Mass(_) | FoodQuantity::Volume(_) | FoodQuantity::Serving(_) | FoodQuantity::Portion(_, _) => self.clone(),
+			FoodQuantity::Kilograms(kg) => FoodQuantity::Mass(kg * GRAMS_PER_KILOGRAM),
+			FoodQuantity::Ounces(oz) => FoodQuantity::Mass(oz * GRAMS_PER_OUNCE),
+			FoodQuantity::Pounds(lb) => FoodQuantity::Mass(lb * GRAMS_PER_POUND),
+			FoodQuantity::Litres(l) => FoodQuantity::Volume(l * ML_PER_LITRE),
+			FoodQuantity::Cups(c) => FoodQuantity::Volume(c * ML_PER_CUP),
+			FoodQuantity::Tablespoons(tbsp) => FoodQuantity::Volume(tbsp * ML_PER_TABLESPOON),
+			FoodQuantity::Teaspoons(tsp) => FoodQuantity::Volume(tsp * ML_PER_TEASPOON),
+			FoodQuantity::FluidOunces(floz) => FoodQuantity::Volume(floz * ML_PER_FLUID_OUNCE),
+		}
+	}
+
+	/// The number, in whatever unit this quantity is in.
+	pub fn amount(&self) -> f32 {
+		match *self {
+			FoodQuantity::Mass(v) | FoodQuantity::Volume(v) | FoodQuantity::Serving(v) | FoodQuantity::Portion(_, v)
+			| FoodQuantity::Kilograms(v) | FoodQuantity::Ounces(v) | FoodQuantity::Pounds(v)
+			| FoodQuantity::Litres(v) | FoodQuantity::Cups(v) | FoodQuantity::Tablespoons(v)
+			| FoodQuantity::Teaspoons(v) | FoodQuantity::FluidOunces(v) => v,
+		}
+	}
+
+	pub fn unit_name(&self) -> &str {
+		match self {
+			FoodQuantity::Mass(_) => "g",
+			FoodQuantity::Volume(_) => "ml",
+			FoodQuantity::Serving(_) => "serving",
+			FoodQuantity::Portion(name, _) => name,
+			FoodQuantity::Kilograms(_) => "kg",
+			FoodQuantity::Ounces(_) => "oz",
+			FoodQuantity::Pounds(_) => "lb",
+			FoodQuantity::Litres(_) => "l",
+			FoodQuantity::Cups(_) => "cup",
+			FoodQuantity::Tablespoons(_) => "tbsp",
+			FoodQuantity::Teaspoons(_) => "tsp",
+			FoodQuantity::FluidOunces(_) => "fl oz",
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuantityParseErrorKind {
+	Empty,
+	MissingNumber, // The text doesn't start with an amount.
+	InvalidNumber,
+	UnknownUnit,
+}
+
+/// Why a quantity couldn't be parsed, and which bytes of the input are to blame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantityParseError {
+	pub kind: QuantityParseErrorKind,
+	pub span: Range<usize>,
+}
+
+impl fmt::Display for QuantityParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let reason = match self.kind {
+			QuantityParseErrorKind::Empty => "no quantity given",
+			QuantityParseErrorKind::MissingNumber => "expected an amount like 2, 1.5, 1 1/2 or ½",
+			QuantityParseErrorKind::InvalidNumber => "not a valid amount",
+			QuantityParseErrorKind::UnknownUnit => "unknown unit",
+		};
+		write!(f, "{} (at {}..{})", reason, self.span.start, self.span.end)
+	}
+}
+
+impl std::error::Error for QuantityParseError {}
+
+const UNICODE_FRACTIONS: [(char, f32); 18] = [
+	('¼', 1.0/4.0), ('½', 1.0/2.0), ('¾', 3.0/4.0),
+	('⅓', 1.0/3.0), ('⅔', 2.0/3.0),
+	('⅕', 1.0/5.0), ('⅖', 2.0/5.0), ('⅗', 3.0/5.0), ('⅘', 4.0/5.0),
+	('⅙', 1.0/6.0), ('⅚', 5.0/6.0),
+	('⅐', 1.0/7.0),
+	('⅛', 1.0/8.0), ('⅜', 3.0/8.0), ('⅝', 5.0/8.0), ('⅞', 7.0/8.0),
+	('⅑', 1.0/9.0), ('⅒', 1.0/10.0),
+];
+
+type QuantityConstructor = fn(f32) -> FoodQuantity;
+
+// Singular spellings.  Plurals are handled by stripping a trailing 's' or 'es'.
+const UNIT_NAMES: [(&str, QuantityConstructor); 32] = [
+	("g", FoodQuantity::Mass), ("gm", FoodQuantity::Mass), ("gram", FoodQuantity::Mass), ("gramme", FoodQuantity::Mass),
+	("mg", |mg| FoodQuantity::Mass(mg / 1000.0)), ("milligram", |mg| FoodQuantity::Mass(mg / 1000.0)),
+	("kg", FoodQuantity::Kilograms), ("kilo", FoodQuantity::Kilograms), ("kilogram", FoodQuantity::Kilograms),
+	("oz", FoodQuantity::Ounces), ("ounce", FoodQuantity::Ounces),
+	("lb", FoodQuantity::Pounds), ("pound", FoodQuantity::Pounds),
+	("ml", FoodQuantity::Volume), ("cc", FoodQuantity::Volume), ("millilitre", FoodQuantity::Volume), ("milliliter", FoodQuantity::Volume),
+	("l", FoodQuantity::Litres), ("litre", FoodQuantity::Litres), ("liter", FoodQuantity::Litres),
+	("c", FoodQuantity::Cups), ("cup", FoodQuantity::Cups),
+	("tbsp", FoodQuantity::Tablespoons), ("tbs", FoodQuantity::Tablespoons), ("tablespoon", FoodQuantity::Tablespoons),
+	("tsp", FoodQuantity::Teaspoons), ("teaspoon", FoodQuantity::Teaspoons),
+	("fl oz", FoodQuantity::FluidOunces), ("floz", FoodQuantity::FluidOunces), ("fluid ounce", FoodQuantity::FluidOunces),
+	("serving", FoodQuantity::Serving), ("portion", FoodQuantity::Serving),
+];
+
+// Generic counts.  A food's own portion names (slice, egg, ...) are a separate concern; these all mean "one serving".
+const SERVING_WORDS: [&str; 6] = ["piece", "pc", "slice", "each", "ea", "item"];
+
+fn unit_constructor(unit: &str) -> Option<QuantityConstructor> {
+	// Old recipes use 'T' for tablespoons and 't' for teaspoons, so check those before case folding.
+	match unit {
+		"T" => { return Some(FoodQuantity::Tablespoons); },
+		"t" => { return Some(FoodQuantity::Teaspoons); },
+		_ => {}
+	}
+	let normalized = unit.to_lowercase().replace('.', "").split_whitespace().collect::<Vec<&str>>().join(" ");
+	let singulars = [Some(normalized.as_str()), normalized.strip_suffix("es"), normalized.strip_suffix('s')];
+	for candidate in singulars.iter().flatten() {
+		if let Some((_, constructor)) = UNIT_NAMES.iter().find(|(name, _)| name == candidate) {
+			return Some(*constructor);
+		}
+		if SERVING_WORDS.contains(candidate) {
+			return Some(FoodQuantity::Serving);
+		}
+	}
+	None
+}
+
+fn unicode_fraction(c: char) -> Option<f32> {
+	UNICODE_FRACTIONS.iter().find(|(f, _)| *f == c).map(|(_, v)| *v)
+}
+
+// Small cursor over the input so every error can report the bytes it was looking at.
+struct QuantityScanner<'a> {
+	text: &'a str,
+	pos: usize,
+}
+
+impl<'a> QuantityScanner<'a> {
+	fn peek(&self) -> Option<char> {
+		self.text[self.pos..].chars().next()
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some(c) = self.peek() {
+			if !c.is_whitespace() {
+				break;
+			}
+			self.pos += c.len_utf8();
+		}
+	}
+
+	fn take_while(&mut self, accept: impl Fn(char) -> bool) -> Range<usize> {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+			if !accept(c) {
+				break;
+			}
+			self.pos += c.len_utf8();
+		}
+		start..self.pos
+	}
+
+	fn error(&self, kind: QuantityParseErrorKind, span: Range<usize>) -> QuantityParseError {
+		QuantityParseError { kind, span }
+	}
+
+	// digits '/' digits, with the numerator already consumed.
+	fn finish_fraction(&mut self, numerator: Range<usize>) -> Result<f32, QuantityParseError> {
+		self.pos += self.peek().map(|c| c.len_utf8()).unwrap_or(0); // The slash.
+		let denominator = self.take_while(|c| c.is_ascii_digit());
+		let span = numerator.start..denominator.end;
+		let top = self.text[numerator].parse::<u32>().map_err(|_| self.error(QuantityParseErrorKind::InvalidNumber, span.clone()))?;
+		let bottom = self.text[denominator].parse::<u32>().map_err(|_| self.error(QuantityParseErrorKind::InvalidNumber, span.clone()))?;
+		if bottom == 0 {
+			return Err(self.error(QuantityParseErrorKind::InvalidNumber, span));
+		}
+		Ok(top as f32 / bottom as f32)
+	}
+
+	// Accepts "2", "1.5", ".5", "1/2", "½", "1½", "1 ½", "1 1/2" and "1-1/2".
+	fn amount(&mut self) -> Result<f32, QuantityParseError> {
+		let is_slash = |c: Option<char>| c == Some('/') || c == Some('⁄');
+		let whole = self.take_while(|c| c.is_ascii_digit() || c == '.');
+		if whole.is_empty() {
+			return match self.peek().and_then(unicode_fraction) {
+				Some(v) => {
+					self.pos += self.peek().map(|c| c.len_utf8()).unwrap_or(0);
+					Ok(v)
+				},
+				None => {
+					let word = self.take_while(|c| !c.is_whitespace());
+					Err(self.error(QuantityParseErrorKind::MissingNumber, word))
+				}
+			};
+		}
+		if is_slash(self.peek()) {
+			return self.finish_fraction(whole);
+		}
+		let mut value = self.text[whole.clone()].parse::<f32>().map_err(|_| self.error(QuantityParseErrorKind::InvalidNumber, whole.clone()))?;
+		if self.text[whole].contains('.') {
+			return Ok(value);
+		}
+
+		// Maybe a mixed number.  Back out if what follows isn't a fraction.
+		let after_whole = self.pos;
+		if self.peek() == Some('-') {
+			self.pos += 1;
+		} else {
+			self.skip_whitespace();
+		}
+		if let Some(v) = self.peek().and_then(unicode_fraction) {
+			self.pos += self.peek().map(|c| c.len_utf8()).unwrap_or(0);
+			value += v;
+		} else {
+			let numerator = self.take_while(|c| c.is_ascii_digit());
+			if !numerator.is_empty() && is_slash(self.peek()) {
+				value += self.finish_fraction(numerator)?;
+			} else {
+				self.pos = after_whole;
+			}
+		}
+		Ok(value)
+	}
+}
+
+// Shared by FoodQuantity::from_str and Food::parse_quantity.  `portion` maps unit text to one of a food's portion names.
+fn parse_quantity(text: &str, portion: impl Fn(&str) -> Option<String>) -> Result<FoodQuantity, QuantityParseError> {
+	let mut scanner = QuantityScanner { text, pos: 0 };
+	scanner.skip_whitespace();
+	if scanner.pos == text.len() {
+		return Err(scanner.error(QuantityParseErrorKind::Empty, 0..text.len()));
+	}
+	let amount = scanner.amount()?;
+
+	scanner.skip_whitespace();
+	let unit_start = scanner.pos;
+	let unit = text[unit_start..].trim_end();
+	if unit.is_empty() {
+		return Ok(FoodQuantity::Serving(amount));
+	}
+	// A food's own portions win over generic units, so "2 slices" of bread means bread slices, not servings.
+	if let Some(name) = portion(unit) {
+		return Ok(FoodQuantity::Portion(name, amount));
+	}
+	match unit_constructor(unit) {
+		Some(constructor) => Ok(constructor(amount)),
+		None => Err(scanner.error(QuantityParseErrorKind::UnknownUnit, unit_start..unit_start + unit.len())),
+	}
+}
+
+impl FromStr for FoodQuantity {
+	type Err = QuantityParseError;
+
+	/// Parse quantities like "100g", "1 1/2 cups", "½ tsp", "3 oz" or "2 slices".  A bare number means servings.
+	/// Use Food::parse_quantity to also understand that food's named portions.
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		parse_quantity(text, |_| None)
+	}
+}
+
+/// A named amount of a particular food, like "slice" or "cup, chopped".
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Portion {
+	pub name: String,
+	pub grams: f32,
+}
+
+impl Portion {
+	/// Build a portion from a household measure like "1 cup, chopped" or "1/2 large" and the weight of that many.
+	/// A measure without a number counts as one.
+	pub fn from_measure(measure: &str, grams: f32) -> Option<Portion> {
+		let mut scanner = QuantityScanner { text: measure, pos: 0 };
+		scanner.skip_whitespace();
+		let count = match scanner.amount() {
+			Ok(count) => count,
+			Err(_) => { scanner.pos = 0; 1.0 },
+		};
+		let name = measure[scanner.pos..].trim();
+		if name.is_empty() || count <= 0.0 || grams.is_nan() || grams <= 0.0 {
+			return None;
+		}
+		Some(Portion { name: name.to_string(), grams: grams / count })
+	}
+
+	/// Millilitres in one of this portion, if its name is a volume like "cup" or "tbsp, chopped".
+	pub fn volume(&self) -> Option<f32> {
+		let unit = self.name.split(',').next().unwrap_or("");
+		match unit_constructor(unit.trim())?(1.0).canonical() {
+			FoodQuantity::Volume(ml) => Some(ml),
+			_ => None,
+		}
+	}
+}
+
+// Lowercase, drop punctuation, collapse whitespace.
+fn normalize_portion_name(name: &str) -> String {
+	name.to_lowercase().replace([',', '.'], " ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// "cups chopped" and "large eggs" should find "cup, chopped" and "large egg".  Try the text as-is, then with the first
+// or last word made singular.
+fn portion_name_variants(text: &str) -> Vec<String> {
+	let normalized = normalize_portion_name(text);
+	let mut variants = vec![normalized.clone()];
+	let words: Vec<&str> = normalized.split(' ').collect();
+	for idx in [0, words.len() - 1].iter() {
+		for suffix in ["es", "s"].iter() {
+			if let Some(singular) = words[*idx].strip_suffix(*suffix) {
+				let mut changed = words.clone();
+				changed[*idx] = singular;
+				variants.push(changed.join(" "));
+			}
+		}
+	}
+	variants
 }
 
 pub type FoodID = u64;
@@ -24,12 +362,17 @@ pub struct Food {
 
 	pub mass: u32, // Should be 100g.  This is NOT molar mass.  Just a scalar, just in case.
 	pub volume_of_100g: f32, // What is 100g in ml / cm^3?  This is the reciprocal of 'density', sometimes called 'specific volume'.
+	pub volume_estimated: bool, // volume_of_100g was guessed from the food's category, not measured.  See FoodDB::estimate_missing_densities.
 	pub servings_in_100g: f32, // How many servings is 100g?
 
 	// Remove is_composite because can say this is true from ingredients being non-empty.
 	//is_composite: bool, // Is this just 'defined' as something, or is this a product of other foods?  
 	pub user_defined: bool,
 	pub ingredients: Vec<(FoodID, FoodQuantity)>,
+	pub cooked_mass: Option<f32>, // Grams of the finished batch, for recipes that gain or lose water.  None uses the raw ingredient total.
+	pub portions: Vec<Portion>, // Named serving sizes.  servings_in_100g is still the default, anonymous one.
+	pub barcodes: Vec<String>, // GTIN/UPC/EAN codes printed on the packaging, as 13 digits.  See normalize_barcode and FoodDB::add_barcode.
+	pub source: String, // Where an imported food came from, like "fdc:171265".  Empty for foods entered by hand.
 }
 
 impl Clone for Food {
@@ -45,10 +388,15 @@ impl Clone for Food {
 			
 			mass: self.mass,
 			volume_of_100g: self.volume_of_100g,
+			volume_estimated: self.volume_estimated,
 			servings_in_100g: self.servings_in_100g,
 			
 			user_defined: self.user_defined,
 			ingredients: self.ingredients.clone(),
+			cooked_mass: self.cooked_mass,
+			portions: self.portions.clone(),
+			barcodes: self.barcodes.clone(),
+			source: self.source.clone(),
 		}
 	}
 }
@@ -64,26 +412,86 @@ impl Default for Food {
 			nutrition: Nutrients::default(),
 			mass: 100,
 			volume_of_100g: 0.0,
+			volume_estimated: false,
 			servings_in_100g: 0.0, // If 1 serving is 200g, this is 0.5.  100 / mass_per_serving.
 			user_defined: false,
-			ingredients: vec![]
+			ingredients: vec![],
+			cooked_mass: None,
+			portions: vec![],
+			barcodes: vec![],
+			source: String::new(),
 		}
 	}
 }
 
 impl Food {
+	/// Find a named portion, forgiving case, punctuation and plurals.
+	pub fn portion(&self, name:&str) -> Option<&Portion> {
+		let variants = portion_name_variants(name);
+		self.portions.iter().find(|p| variants.contains(&normalize_portion_name(&p.name)))
+	}
+
+	/// Like FoodQuantity::from_str, but also understands this food's named portions.
+	pub fn parse_quantity(&self, text:&str) -> Result<FoodQuantity, QuantityParseError> {
+		parse_quantity(text, |unit| self.portion(unit).map(|p| p.name.clone()))
+	}
+
+	/// Nutrients in `amount` of this food.  Gives infinite or NaN values if the amount can't be converted to grams.
+	/// Prefer try_get_nutrition.
 	pub fn get_nutrition(&self, amount:FoodQuantity) -> Nutrients {
 		// Foods should be in 100g servings.
-		let nutrients = self.nutrition.clone();
+		self.nutrition.clone() * self.scale_factor(amount)
+	}
 
-		// Convert the amount in scale factor to a 100g equivalent.
-		let scale_factor = match amount {
-			FoodQuantity::Mass(grams) => { (grams as f32) / (self.mass as f32) },
-			FoodQuantity::Volume(cm3) => { cm3 / self.volume_of_100g },
-			FoodQuantity::Serving(servings) => { servings / self.servings_in_100g }
-		};
+	pub fn try_get_nutrition(&self, amount:&FoodQuantity) -> Result<Nutrients, Error> {
+		Ok(self.nutrition.clone() * self.try_scale_factor(amount)?)
+	}
+
+	/// How many multiples of `mass` (i.e., 100g) the given amount is.  NaN if there's no way to tell.
+	pub fn scale_factor(&self, amount:FoodQuantity) -> f32 {
+		self.try_scale_factor(&amount).unwrap_or(f32::NAN)
+	}
+
+	/// Like scale_factor, but says which conversion is missing instead of returning NaN.
+	pub fn try_scale_factor(&self, amount:&FoodQuantity) -> Result<f32, Error> {
+		let missing = |what: String| Error::MissingConversion { food: self.id, missing: what };
+		let mass = self.mass as f32;
+		if mass <= 0.0 {
+			return Err(missing("reference mass (mass)".to_string()));
+		}
+		match amount.canonical() {
+			FoodQuantity::Mass(grams) => Ok(grams / mass),
+			FoodQuantity::Volume(cm3) => {
+				if !self.has_density() {
+					return Err(missing("density (volume_of_100g)".to_string()));
+				}
+				Ok(cm3 / self.volume_of_100g)
+			},
+			FoodQuantity::Serving(servings) => {
+				if !(self.servings_in_100g.is_finite() && self.servings_in_100g > 0.0) {
+					return Err(missing("serving size (servings_in_100g)".to_string()));
+				}
+				Ok(servings / self.servings_in_100g)
+			},
+			FoodQuantity::Portion(name, count) => match self.portion(&name) {
+				Some(p) => Ok(p.grams * count / mass),
+				None => Err(missing(format!("portion called '{}'", name))),
+			},
+			_ => unreachable!("canonical() only returns mass, volume, servings, or portions."),
+		}
+	}
 
-		nutrients * scale_factor
+	pub fn to_grams(&self, amount:FoodQuantity) -> f32 {
+		self.scale_factor(amount) * (self.mass as f32)
+	}
+
+	pub fn try_to_grams(&self, amount:&FoodQuantity) -> Result<f32, Error> {
+		Ok(self.try_scale_factor(amount)? * (self.mass as f32))
+	}
+
+	/// Composite foods (recipes) get their nutrition from their ingredients.  See FoodDB::resolve_nutrition.
+	pub fn is_composite(&self) -> bool {
+		!self.ingredients.is_empty()
 	}
 }
 
@@ -91,26 +499,149 @@ impl Food {
 #[cfg(test)]
 mod tests {
 	use crate::food::*;
+	use crate::nutrition::EnergyUnit;
 
 	#[test]
 	fn test_amount_scaling() {
-		let mut sugar = Food::default();
-		sugar.name = String::from("sugar");
-
-		sugar.mass = 100; // This should be 100 by default.
-		sugar.volume_of_100g = 118.29; // Some say 0.7g/cm^3 density, which is 1.428cm^3/g specific volume -> x100g = 142.8cm^3.  Going with 118.29.
-		sugar.servings_in_100g = 3.57; // A serving is 28g.  3.57 servings = 100g.
+		let mut sugar = Food {
+			name: String::from("sugar"),
+			mass: 100, // This should be 100 by default.
+			volume_of_100g: 118.29, // Some say 0.7g/cm^3 density, which is 1.428cm^3/g specific volume -> x100g = 142.8cm^3.  Going with 118.29.
+			servings_in_100g: 3.57, // A serving is 28g.  3.57 servings = 100g.
+			..Food::default()
+		};
 
-		sugar.nutrition.calories = 387; // 387 calories per 100g.
+		sugar.nutrition.calories = 387.0; // 387 calories per 100g.
 		sugar.nutrition.carbohydrates = 10.0;
 		sugar.nutrition.fats = 10.0;
 		sugar.nutrition.proteins = 10.0;
 
 		// Scale sugar.
-		let sugar_5g = sugar.get_nutrition(FoodQuantity::Mass(5));
+		let sugar_5g = sugar.get_nutrition(FoodQuantity::Mass(5.0));
 		let sugar_1tsp = sugar.get_nutrition(FoodQuantity::Volume(4.9f32)); // 1 tsp = 4.9ml^2
 		// 16 calories in 1tsp sugar.  (Which is about 4.2g)
-		assert_eq!(sugar_5g.calories, 387/20);
-		assert_eq!(sugar_1tsp.calories, 16);
+		assert!((sugar_5g.calories - 387.0/20.0).abs() < 1e-4);
+		assert_eq!(sugar_1tsp.rounded_energy(EnergyUnit::Kilocalories), 16);
+		assert_eq!(sugar.get_nutrition(FoodQuantity::Teaspoons(1.0)).rounded_energy(EnergyUnit::Kilocalories), 16);
+	}
+
+	#[test]
+	fn test_kitchen_units() {
+		let flour = Food {
+			name: String::from("flour"),
+			volume_of_100g: 189.0, // About 125g per cup.
+			..Food::default()
+		};
+
+		assert!((flour.to_grams(FoodQuantity::Cups(1.0)) - 125.18).abs() < 0.01);
+		assert!((flour.to_grams(FoodQuantity::Ounces(4.0)) - 113.4).abs() < 0.01);
+		assert!((flour.to_grams(FoodQuantity::Pounds(1.0)) - 453.59).abs() < 0.01);
+		assert!((flour.to_grams(FoodQuantity::Kilograms(0.25)) - 250.0).abs() < 0.01);
+		assert!((flour.to_grams(FoodQuantity::Mass(2.5)) - 2.5).abs() < 1e-6);
+		assert!((FoodQuantity::Tablespoons(1.0).canonical().amount() - FoodQuantity::Teaspoons(3.0).canonical().amount()).abs() < 1e-4);
+		assert_eq!(FoodQuantity::Litres(1.0).canonical(), FoodQuantity::Volume(1000.0));
+		assert!((FoodQuantity::Cups(1.0).canonical().amount() - FoodQuantity::FluidOunces(8.0).canonical().amount()).abs() < 1e-3);
+		assert!((FoodQuantity::Cups(1.0).canonical().amount() - FoodQuantity::Tablespoons(16.0).canonical().amount()).abs() < 1e-3);
+		assert_eq!(FoodQuantity::FluidOunces(2.0).unit_name(), "fl oz");
+	}
+
+	#[test]
+	fn test_parse_quantity() {
+		let parse = |text: &str| text.parse::<FoodQuantity>();
+		assert_eq!(parse("100g"), Ok(FoodQuantity::Mass(100.0)));
+		assert_eq!(parse("2.5 grams"), Ok(FoodQuantity::Mass(2.5)));
+		assert_eq!(parse("500 mg"), Ok(FoodQuantity::Mass(0.5)));
+		assert_eq!(parse("3 oz"), Ok(FoodQuantity::Ounces(3.0)));
+		assert_eq!(parse("1 lb"), Ok(FoodQuantity::Pounds(1.0)));
+		assert_eq!(parse("2 lbs."), Ok(FoodQuantity::Pounds(2.0)));
+		assert_eq!(parse("1 1/2 cups"), Ok(FoodQuantity::Cups(1.5)));
+		assert_eq!(parse("1-1/2 Cups"), Ok(FoodQuantity::Cups(1.5)));
+		assert_eq!(parse("1½ cup"), Ok(FoodQuantity::Cups(1.5)));
+		assert_eq!(parse("2 ¼ tsp"), Ok(FoodQuantity::Teaspoons(2.25)));
+		assert_eq!(parse("½ tsp"), Ok(FoodQuantity::Teaspoons(0.5)));
+		assert_eq!(parse("3/4 tablespoons"), Ok(FoodQuantity::Tablespoons(0.75)));
+		assert_eq!(parse(".5 l"), Ok(FoodQuantity::Litres(0.5)));
+		assert_eq!(parse("1 T"), Ok(FoodQuantity::Tablespoons(1.0)));
+		assert_eq!(parse("1 t"), Ok(FoodQuantity::Teaspoons(1.0)));
+		assert_eq!(parse("8 fl. oz."), Ok(FoodQuantity::FluidOunces(8.0)));
+		assert_eq!(parse("2 fluid ounces"), Ok(FoodQuantity::FluidOunces(2.0)));
+		assert_eq!(parse("250ml"), Ok(FoodQuantity::Volume(250.0)));
+		assert_eq!(parse("2 slices"), Ok(FoodQuantity::Serving(2.0)));
+		assert_eq!(parse("1 serving"), Ok(FoodQuantity::Serving(1.0)));
+		assert_eq!(parse("  3  "), Ok(FoodQuantity::Serving(3.0)));
+		assert_eq!(parse("2 1/2"), Ok(FoodQuantity::Serving(2.5)));
+	}
+
+	#[test]
+	fn test_named_portions() {
+		let bread = Food {
+			name: String::from("bread"),
+			portions: vec![
+				Portion { name: "slice".to_string(), grams: 32.0 },
+				Portion { name: "cup, cubes".to_string(), grams: 30.0 },
+				Portion { name: "large roll".to_string(), grams: 60.0 },
+			],
+			..Food::default()
+		};
+
+		assert_eq!(bread.parse_quantity("2 slices"), Ok(FoodQuantity::Portion("slice".to_string(), 2.0)));
+		assert_eq!(bread.parse_quantity("1 Slice"), Ok(FoodQuantity::Portion("slice".to_string(), 1.0)));
+		assert_eq!(bread.parse_quantity("3 cups cubes"), Ok(FoodQuantity::Portion("cup, cubes".to_string(), 3.0)));
+		assert_eq!(bread.parse_quantity("1½ large rolls"), Ok(FoodQuantity::Portion("large roll".to_string(), 1.5)));
+		// Generic units still work, and "cup" alone isn't one of bread's portions.
+		assert_eq!(bread.parse_quantity("2 cups"), Ok(FoodQuantity::Cups(2.0)));
+		assert_eq!(bread.parse_quantity("50 g"), Ok(FoodQuantity::Mass(50.0)));
+		assert!(bread.parse_quantity("2 heels").is_err());
+
+		assert!((bread.to_grams(FoodQuantity::Portion("slice".to_string(), 2.0)) - 64.0).abs() < 1e-4);
+		assert!((bread.to_grams(FoodQuantity::Portion("Slices".to_string(), 1.0)) - 32.0).abs() < 1e-4);
+		assert!(bread.to_grams(FoodQuantity::Portion("heel".to_string(), 1.0)).is_nan());
+		assert!(matches!(bread.try_to_grams(&FoodQuantity::Portion("heel".to_string(), 1.0)), Err(Error::MissingConversion { .. })));
+
+		assert_eq!(Portion::from_measure("2 slices", 64.0), Some(Portion { name: "slices".to_string(), grams: 32.0 }));
+		assert_eq!(Portion::from_measure("1/2 cup, cubes", 15.0), Some(Portion { name: "cup, cubes".to_string(), grams: 30.0 }));
+		assert_eq!(Portion::from_measure("large roll", 60.0), Some(Portion { name: "large roll".to_string(), grams: 60.0 }));
+		assert_eq!(Portion::from_measure("1 ", 60.0), None);
+		assert_eq!(Portion::from_measure("1 slice", 0.0), None);
+		assert!((bread.portions[1].volume().unwrap() - 236.59).abs() < 0.01);
+		assert_eq!(bread.portions[0].volume(), None);
+	}
+
+	#[test]
+	fn test_missing_conversions() {
+		let mut oats = Food {
+			name: String::from("oats"),
+			..Food::default()
+		};
+		oats.nutrition.calories = 380.0;
+
+		// Default foods know nothing about density or servings.
+		match oats.try_get_nutrition(&FoodQuantity::Cups(1.0)) {
+			Err(Error::MissingConversion { missing, .. }) => assert!(missing.contains("density")),
+			_ => panic!("Expected a missing density."),
+		}
+		match oats.try_get_nutrition(&FoodQuantity::Serving(1.0)) {
+			Err(Error::MissingConversion { missing, .. }) => assert!(missing.contains("serving")),
+			_ => panic!("Expected a missing serving size."),
+		}
+		assert!(oats.get_nutrition(FoodQuantity::Serving(1.0)).calories.is_nan());
+		assert!((oats.try_get_nutrition(&FoodQuantity::Mass(40.0)).unwrap().calories - 152.0).abs() < 1e-3);
+
+		oats.servings_in_100g = 2.5;
+		assert!((oats.try_get_nutrition(&FoodQuantity::Serving(1.0)).unwrap().calories - 152.0).abs() < 1e-3);
+		oats.mass = 0;
+		assert!(oats.try_get_nutrition(&FoodQuantity::Mass(40.0)).is_err());
+	}
+
+	#[test]
+	fn test_parse_quantity_errors() {
+		let kind_and_span = |text: &str| text.parse::<FoodQuantity>().map_err(|e| (e.kind, e.span)).unwrap_err();
+		assert_eq!(kind_and_span("   "), (QuantityParseErrorKind::Empty, 0..3));
+		assert_eq!(kind_and_span("some sugar"), (QuantityParseErrorKind::MissingNumber, 0..4));
+		assert_eq!(kind_and_span("1.2.3 g"), (QuantityParseErrorKind::InvalidNumber, 0..5));
+		assert_eq!(kind_and_span("1/0 cup"), (QuantityParseErrorKind::InvalidNumber, 0..3));
+		assert_eq!(kind_and_span("2 handfuls"), (QuantityParseErrorKind::UnknownUnit, 2..10));
+		assert_eq!(kind_and_span("½ smidgen "), (QuantityParseErrorKind::UnknownUnit, 3..10));
+		assert!("3 bushels".parse::<FoodQuantity>().unwrap_err().to_string().contains("unknown unit"));
 	}
 }
@@ -0,0 +1,79 @@
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::ops::Add;
+
+/// Hands out IDs that are never reused and remembers where each one currently sits in its backing Vec.
+/// Only the counter is saved.  Positions are rebuilt on load.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct IdIndex<Id: Copy + Eq + Hash> {
+	next_id: Id,
+	#[serde(skip)]
+	positions: HashMap<Id, usize>,
+}
+
+impl<Id> IdIndex<Id> where Id: Copy + Eq + Hash + Ord + Default + Add<Output=Id> + From<u8> {
+	pub fn new() -> Self {
+		IdIndex {
+			next_id: Id::default(),
+			positions: HashMap::new(),
+		}
+	}
+
+	/// Reserve a fresh ID for an item that was just pushed to `position`.
+	pub fn allocate(&mut self, position: usize) -> Id {
+		let id = self.next_id;
+		self.next_id = self.next_id + Id::from(1u8);
+		self.positions.insert(id, position);
+		id
+	}
+
+	pub fn position(&self, id: Id) -> Option<usize> {
+		self.positions.get(&id).copied()
+	}
+
+	/// Recompute every position from the IDs in Vec order.  Call after loading or removing an item.
+	/// Returns the offending ID if two items share one.
+	pub fn rebuild<I: Iterator<Item=Id>>(&mut self, ids: I) -> Result<(), Id> {
+		self.positions.clear();
+		for (position, id) in ids.enumerate() {
+			if self.positions.insert(id, position).is_some() {
+				return Err(id);
+			}
+			// Never hand out an ID that's already on disk, even if the saved counter is stale.
+			if id >= self.next_id {
+				self.next_id = id + Id::from(1u8);
+			}
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::ids::IdIndex;
+
+	#[test]
+	fn ids_are_never_reused() {
+		let mut ids = IdIndex::<u64>::new();
+		let mut items: Vec<u64> = vec![];
+		for _ in 0..3 {
+			items.push(ids.allocate(items.len()));
+		}
+		assert_eq!(items, vec![0, 1, 2]);
+
+		// Remove the last item.  Its ID stays retired.
+		items.pop();
+		ids.rebuild(items.iter().copied()).unwrap();
+		assert_eq!(ids.position(2), None);
+		assert_eq!(ids.allocate(items.len()), 3);
+
+		// Imported IDs push the counter past them.
+		ids.rebuild(vec![40, 7].into_iter()).unwrap();
+		assert_eq!(ids.position(7), Some(1));
+		assert_eq!(ids.allocate(2), 41);
+
+		assert_eq!(ids.rebuild(vec![1, 2, 1].into_iter()), Err(1));
+	}
+}
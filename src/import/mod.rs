@@ -0,0 +1,130 @@
+// Readers for third-party food lists.  Each one turns a file into Food records for FoodDB::import_foods.
+
+use hashbrown::HashMap;
+
+use crate::barcode::normalize_barcode;
+use crate::error::{Error, Result};
+use crate::food::{Food, Portion};
+use crate::FoodDB;
+
+pub mod myfooddata;
+pub mod openfoodfacts;
+pub mod usda;
+
+/// How many foods an import added, and how many it refreshed in place.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+	pub added: usize,
+	pub updated: usize,
+}
+
+impl FoodDB {
+	/// Merge imported foods into the database.  A food that was imported before (same `source`, or failing that, one
+	/// of the same barcodes) is updated in place and keeps its ID, so meals and recipes using it still work.
+	/// Everything else is added with a fresh ID.  Foods entered by hand are never matched.
+	/// Cached meal and recipe totals aren't touched; see recompute_all_meal_nutrients.
+	pub fn import_foods<I: IntoIterator<Item=Food>>(&mut self, foods: I) -> ImportSummary {
+		let mut summary = ImportSummary::default();
+		let mut previously_imported: HashMap<String, usize> = HashMap::new();
+		for (position, food) in self.foods.iter().enumerate().filter(|(_, f)| !f.source.is_empty()) {
+			for key in match_keys(food) {
+				previously_imported.insert(key, position);
+			}
+		}
+
+		for mut food in foods {
+			let keys = match_keys(&food);
+			let position = match keys.iter().find_map(|key| previously_imported.get(key)) {
+				Some(&position) => {
+					refresh_imported_food(&mut self.foods[position], food);
+					summary.updated += 1;
+					position
+				},
+				None => {
+					let position = self.foods.len();
+					food.id = self.food_ids.allocate(position);
+					self.foods.push(food);
+					summary.added += 1;
+					position
+				},
+			};
+			for key in keys {
+				previously_imported.insert(key, position);
+			}
+		}
+		self.reindex_foods();
+		summary
+	}
+}
+
+// The source comes first so it wins over a barcode that moved between products.
+fn match_keys(food: &Food) -> Vec<String> {
+	let source = Some(food.source.clone()).filter(|s| !s.is_empty()).map(|s| format!("source:{}", s));
+	let barcodes = food.barcodes.iter().map(|b| format!("barcode:{}", normalize_barcode(b).unwrap_or_else(|_| b.clone())));
+	source.into_iter().chain(barcodes).collect()
+}
+
+// Take everything the source knows about.  The ID, recipe details and any density measured by hand stay.
+fn refresh_imported_food(existing: &mut Food, incoming: Food) {
+	if incoming.has_density() || !existing.has_density() {
+		existing.volume_of_100g = incoming.volume_of_100g;
+		existing.volume_estimated = incoming.volume_estimated;
+	}
+	existing.name = incoming.name;
+	existing.manufacturer = incoming.manufacturer;
+	existing.tags = incoming.tags;
+	existing.nutrition = incoming.nutrition;
+	existing.mass = incoming.mass;
+	existing.servings_in_100g = incoming.servings_in_100g;
+	existing.portions = incoming.portions;
+	existing.barcodes = incoming.barcodes;
+	existing.source = incoming.source;
+}
+
+/// The `Food::source` of a USDA FoodData Central food.
+pub fn fdc_source(fdc_id: u64) -> String {
+	format!("fdc:{}", fdc_id)
+}
+
+// Finds CSV columns by header, ignoring case and stray whitespace, so small changes between releases don't break us.
+pub(crate) struct CsvColumns {
+	by_name: HashMap<String, usize>,
+}
+
+impl CsvColumns {
+	pub fn new(headers: &csv::StringRecord) -> Self {
+		CsvColumns {
+			by_name: headers.iter().enumerate().map(|(idx, h)| (normalize_header(h), idx)).collect(),
+		}
+	}
+
+	/// The trimmed cell, or None if the column is missing or the cell is blank.
+	pub fn text<'r>(&self, record: &'r csv::StringRecord, column: &str) -> Option<&'r str> {
+		let idx = *self.by_name.get(&normalize_header(column))?;
+		record.get(idx).map(|cell| cell.trim()).filter(|cell| !cell.is_empty())
+	}
+
+	/// A numeric cell.  Blank is None, since unknown and zero aren't the same thing.
+	pub fn number(&self, record: &csv::StringRecord, column: &str) -> Result<Option<f32>> {
+		match self.text(record, column) {
+			None => Ok(None),
+			Some(cell) => cell.parse::<f32>().map(Some).map_err(|_| Error::Import {
+				line: line_of(record),
+				message: format!("'{}' is not a number in column '{}'", cell, column),
+			}),
+		}
+	}
+}
+
+fn normalize_header(header: &str) -> String {
+	header.trim_start_matches('\u{feff}').split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+pub(crate) fn line_of(record: &csv::StringRecord) -> u64 {
+	record.position().map(|p| p.line()).unwrap_or(0)
+}
+
+/// Specific volume (ml per 100g) from the first portion measured by volume, like "1 cup" weighing 244g.
+pub(crate) fn volume_of_100g_from_portions(portions: &[Portion]) -> Option<f32> {
+	portions.iter().find_map(|p| p.volume().map(|ml| 100.0 * ml / p.grams))
+}
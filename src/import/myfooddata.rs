@@ -0,0 +1,169 @@
+// MyFoodData's Nutrition Facts Spreadsheet (myfooddata.com), a CSV of USDA foods with every value per 100g.
+// Columns that have nowhere to go in Nutrients (water, copper, the individual amino and fatty acids, ...) and
+// derived ones (net carbs, PRAL score, 200 calorie weight) are skipped.
+
+use std::io::Read;
+
+use crate::error::{Error, Result};
+use crate::food::{Food, Portion};
+use crate::import::{fdc_source, line_of, volume_of_100g_from_portions, CsvColumns};
+use crate::nutrition::Nutrients;
+
+const ID: &str = "ID";
+const NAME: &str = "Name";
+const FOOD_GROUP: &str = "Food Group";
+const CALORIES: &str = "Calories";
+const FAT: &str = "Fat (g)";
+const PROTEIN: &str = "Protein (g)";
+const CARBOHYDRATE: &str = "Carbohydrate (g)";
+
+// The spreadsheet column for each entry of OPTIONAL_NUTRIENTS, in the same order.  Units already match.
+const OPTIONAL_NUTRIENT_COLUMNS: [&str; 24] = [
+	"Fiber (g)",
+	"Sugars (g)",
+	"Added Sugar (g)",
+	"Saturated Fats (g)",
+	"Trans Fatty Acids (g)",
+	"Cholesterol (mg)",
+	"Sodium (mg)",
+	"Potassium, K (mg)",
+	"Calcium (mg)",
+	"Iron, Fe (mg)",
+	"Magnesium (mg)",
+	"Phosphorus, P (mg)",
+	"Zinc, Zn (mg)",
+	"Vitamin A, RAE (mcg)",
+	"Vitamin C (mg)",
+	"Vitamin D (mcg)",
+	"Vitamin E (Alpha-Tocopherol) (mg)",
+	"Vitamin K (mcg)",
+	"Thiamin (B1) (mg)",
+	"Riboflavin (B2) (mg)",
+	"Niacin (B3) (mg)",
+	"Vitamin B6 (mg)",
+	"Folate DFE (mcg)",
+	"Vitamin B-12 (mcg)",
+];
+
+// "Serving Weight 1 (g)" and "Serving Description 1 (g)" through 9.  The first is the default serving.
+const SERVING_COLUMNS: usize = 9;
+
+/// Read every row of the spreadsheet.  Foods carry the spreadsheet's IDs, which are USDA FoodData Central IDs, and
+/// record them in `source` too, so FoodDB::import_foods can update them when a newer spreadsheet is imported.
+pub fn read_foods<R: Read>(reader: R) -> Result<Vec<Food>> {
+	let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+	let columns = CsvColumns::new(csv_reader.headers()?);
+	let mut foods = vec![];
+	for record in csv_reader.records() {
+		foods.push(food_from_record(&columns, &record?)?);
+	}
+	Ok(foods)
+}
+
+fn food_from_record(columns: &CsvColumns, record: &csv::StringRecord) -> Result<Food> {
+	let line = line_of(record);
+	let id = columns.text(record, ID).and_then(|id| id.parse().ok()).ok_or_else(|| Error::Import { line, message: "missing or invalid ID".to_string() })?;
+	let name = columns.text(record, NAME).ok_or_else(|| Error::Import { line, message: format!("food {} has no name", id) })?;
+
+	let mut nutrition = Nutrients {
+		calories: columns.number(record, CALORIES)?.unwrap_or(0.0),
+		fats: columns.number(record, FAT)?.unwrap_or(0.0),
+		carbohydrates: columns.number(record, CARBOHYDRATE)?.unwrap_or(0.0),
+		proteins: columns.number(record, PROTEIN)?.unwrap_or(0.0),
+		..Nutrients::default()
+	};
+	for (value, column) in nutrition.optional_mut().iter_mut().zip(OPTIONAL_NUTRIENT_COLUMNS.iter()) {
+		**value = columns.number(record, column)?;
+	}
+
+	let mut servings = vec![];
+	for idx in 1..=SERVING_COLUMNS {
+		let grams = columns.number(record, &format!("Serving Weight {} (g)", idx))?;
+		let measure = columns.text(record, &format!("Serving Description {} (g)", idx));
+		if let (Some(grams), Some(measure)) = (grams, measure) {
+			servings.push((measure, grams));
+		}
+	}
+	let portions: Vec<Portion> = servings.iter().filter_map(|(measure, grams)| Portion::from_measure(measure, *grams)).collect();
+
+	Ok(Food {
+		id,
+		source: fdc_source(id),
+		name: name.to_string(),
+		tags: columns.text(record, FOOD_GROUP).map(|group| group.replace('|', "/")).unwrap_or_default(),
+		nutrition,
+		volume_of_100g: volume_of_100g_from_portions(&portions).unwrap_or(0.0),
+		servings_in_100g: servings.first().filter(|(_, grams)| *grams > 0.0).map(|(_, grams)| 100.0 / grams).unwrap_or(0.0),
+		user_defined: false,
+		portions,
+		..Food::default()
+	})
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use crate::import::{myfooddata, ImportSummary};
+
+	const SAMPLE: &str = include_str!("../../tests/fixtures/myfooddata_sample.csv");
+
+	#[test]
+	fn every_column_lands_somewhere() {
+		let foods = myfooddata::read_foods(SAMPLE.as_bytes()).unwrap();
+		assert_eq!(foods.len(), 3);
+
+		let milk = &foods[0];
+		assert_eq!(milk.id, 171265);
+		assert_eq!(milk.name, "Milk, whole");
+		assert_eq!(milk.tags, "Dairy and Egg Products");
+		assert_eq!(milk.source, "fdc:171265");
+		assert_eq!(milk.nutrition.calories, 61.0);
+		assert_eq!(milk.nutrition.proteins, 3.27);
+		assert_eq!(milk.nutrition.calcium, Some(113.0));
+		assert_eq!(milk.nutrition.vitamin_b12, Some(0.45));
+		assert_eq!(milk.nutrition.added_sugars, None); // Blank means unknown.
+		assert_eq!(milk.portions.len(), 3);
+		assert_eq!(milk.portions[0], Portion { name: "cup".to_string(), grams: 244.0 });
+		assert_eq!(milk.portions[2], Portion { name: "fl oz".to_string(), grams: 30.5 });
+		assert!((milk.servings_in_100g - 100.0 / 244.0).abs() < 1e-6);
+		// One cup weighs 244g, so 100g is about 97ml.
+		assert!((milk.volume_of_100g - 96.96).abs() < 0.01);
+		assert!(!milk.volume_estimated);
+
+		// No volume measures, so the density is left for FoodDB::estimate_missing_densities.
+		let oats = &foods[2];
+		assert_eq!(oats.volume_of_100g, 0.0);
+		assert_eq!(oats.portions[0], Portion { name: "oz".to_string(), grams: 28.35 });
+
+		let mut db = FoodDB::new();
+		assert_eq!(db.import_foods(foods), ImportSummary { added: 3, updated: 0 });
+		assert_eq!(db.estimate_missing_densities(), 1);
+		let by_source = |db: &FoodDB, source: &str| db.foods.iter().find(|f| f.source == source).unwrap().id;
+		let (milk, oats) = (by_source(&db, "fdc:171265"), by_source(&db, "fdc:173904"));
+		assert!(db.food(oats).unwrap().volume_estimated);
+		assert_eq!(db.get_autocomplete_suggestions("spin".to_string()).len(), 1);
+		assert!(db.resolve_nutrition(milk, FoodQuantity::Portion("cups".to_string(), 1.0)).is_ok());
+
+		// Importing the spreadsheet again updates the same foods.
+		let mut again = myfooddata::read_foods(SAMPLE.as_bytes()).unwrap();
+		again[0].nutrition.calories = 62.0;
+		assert_eq!(db.import_foods(again), ImportSummary { added: 0, updated: 3 });
+		assert_eq!(db.foods.len(), 3);
+		assert_eq!(by_source(&db, "fdc:171265"), milk);
+		assert_eq!(db.food(milk).unwrap().nutrition.calories, 62.0);
+	}
+
+	#[test]
+	fn bad_rows_name_their_line() {
+		let csv = "ID,name,Calories\n1,Apple,52\n2,Pear,lots\n";
+		match myfooddata::read_foods(csv.as_bytes()) {
+			Err(Error::Import { line, message }) => {
+				assert_eq!(line, 3);
+				assert!(message.contains("lots"));
+			},
+			_ => panic!("Expected an import error."),
+		}
+		assert!(matches!(myfooddata::read_foods("ID,name\n,Nameless\n".as_bytes()), Err(Error::Import { line: 2, .. })));
+	}
+}
@@ -0,0 +1,340 @@
+// Open Food Facts exports (world.openfoodfacts.org/data): the JSONL dump, one product per line, or the tab-separated
+// CSV.  Both are tens of GB, so products are read one at a time and only the ones that pass a Filter are kept.
+// Every `*_100g` nutriment is in grams except energy, which is kJ (`energy_100g`) or kcal (`energy-kcal_100g`).
+
+use hashbrown::HashMap;
+use serde_json::Value;
+use std::io::{BufRead, Lines, Read};
+
+use crate::barcode::normalize_barcode;
+use crate::error::{Error, Result};
+use crate::food::{Food, FoodQuantity, Portion};
+use crate::import::{volume_of_100g_from_portions, CsvColumns};
+use crate::nutrition::{convert_unit, Nutrients, OPTIONAL_NUTRIENTS};
+
+// Nutriment keys for each entry of OPTIONAL_NUTRIENTS, in the same order.
+const OPTIONAL_NUTRIMENTS: [&str; 24] = [
+	"fiber_100g",
+	"sugars_100g",
+	"added-sugars_100g",
+	"saturated-fat_100g",
+	"trans-fat_100g",
+	"cholesterol_100g",
+	"sodium_100g",
+	"potassium_100g",
+	"calcium_100g",
+	"iron_100g",
+	"magnesium_100g",
+	"phosphorus_100g",
+	"zinc_100g",
+	"vitamin-a_100g",
+	"vitamin-c_100g",
+	"vitamin-d_100g",
+	"vitamin-e_100g",
+	"vitamin-k_100g",
+	"vitamin-b1_100g",
+	"vitamin-b2_100g",
+	"vitamin-pp_100g", // Niacin.
+	"vitamin-b6_100g",
+	"vitamin-b9_100g", // Folate.
+	"vitamin-b12_100g",
+];
+
+const MACRO_NUTRIMENTS: [&str; 6] = ["energy-kcal_100g", "energy_100g", "fat_100g", "carbohydrates_100g", "proteins_100g", "salt_100g"];
+
+// Labels list salt.  Sodium is 40% of it by mass.
+const SODIUM_PER_SALT: f32 = 0.4;
+
+/// Which products to keep.  The default keeps every product with a name and an energy value.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+	/// Keep products sold in any of these countries, e.g. "France", "united-kingdom" or "en:germany".  Empty keeps all.
+	pub countries: Vec<String>,
+	/// Open Food Facts' own 0 to 1 estimate of how filled-in a product page is.
+	pub min_completeness: f32,
+}
+
+// Open Food Facts tags look like "en:united-kingdom".  Match "United Kingdom" and "united-kingdom" too.
+fn normalize_tag(tag: &str) -> String {
+	let tag = tag.trim().to_lowercase();
+	let tag = tag.strip_prefix("en:").unwrap_or(&tag);
+	tag.split(|c: char| c.is_whitespace() || c == '-' || c == '_').filter(|w| !w.is_empty()).collect::<Vec<&str>>().join("-")
+}
+
+impl Filter {
+	fn accepts(&self, product: &Product) -> bool {
+		if product.completeness.unwrap_or(0.0) < self.min_completeness {
+			return false;
+		}
+		self.countries.is_empty() || self.countries.iter().map(|c| normalize_tag(c)).any(|c| product.countries.iter().any(|pc| normalize_tag(pc) == c))
+	}
+}
+
+// The parts of a product we use, from either format.
+#[derive(Default)]
+struct Product {
+	code: String,
+	name: String,
+	brands: String,
+	brand_owner: String,
+	countries: Vec<String>,
+	categories: Vec<String>,
+	allergens: Vec<String>,
+	completeness: Option<f32>,
+	serving_size: String,
+	serving_quantity: Option<f32>,
+	nutriments: HashMap<&'static str, f32>,
+}
+
+impl Product {
+	fn from_json(product: &Value) -> Product {
+		let text = |key: &str| product[key].as_str().unwrap_or("").trim().to_string();
+		let tags = |key: &str| product[key].as_array().map(|tags| tags.iter().filter_map(|t| t.as_str()).map(|t| t.to_string()).collect()).unwrap_or_default();
+		let code = match &product["code"] {
+			Value::Number(n) => n.to_string(),
+			other => other.as_str().unwrap_or("").trim().to_string(),
+		};
+		let nutriments = OPTIONAL_NUTRIMENTS.iter().chain(MACRO_NUTRIMENTS.iter())
+			.filter_map(|key| json_number(&product["nutriments"][*key]).map(|value| (*key, value)))
+			.collect();
+		Product {
+			code,
+			name: text("product_name"),
+			brands: text("brands"),
+			brand_owner: text("brand_owner"),
+			countries: tags("countries_tags"),
+			categories: tags("categories_tags"),
+			allergens: tags("allergens_tags"),
+			completeness: json_number(&product["completeness"]),
+			serving_size: text("serving_size"),
+			serving_quantity: json_number(&product["serving_quantity"]),
+			nutriments,
+		}
+	}
+
+	fn from_csv(columns: &CsvColumns, record: &csv::StringRecord) -> Product {
+		let text = |column: &str| columns.text(record, column).unwrap_or("").to_string();
+		let number = |column: &str| columns.text(record, column).and_then(|n| n.parse::<f32>().ok());
+		let tags = |column: &str| columns.text(record, column).map(|t| t.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()).unwrap_or_default();
+		Product {
+			code: text("code"),
+			name: text("product_name"),
+			brands: text("brands"),
+			brand_owner: text("brand_owner"),
+			countries: tags("countries_tags"),
+			categories: tags("categories_tags"),
+			allergens: tags("allergens"),
+			completeness: number("completeness"),
+			serving_size: text("serving_size"),
+			serving_quantity: number("serving_quantity"),
+			nutriments: OPTIONAL_NUTRIMENTS.iter().chain(MACRO_NUTRIMENTS.iter()).filter_map(|key| number(key).map(|value| (*key, value))).collect(),
+		}
+	}
+
+	// None for products we can't log: no barcode, no name, or no energy.
+	fn to_food(&self) -> Option<Food> {
+		if self.code.is_empty() || self.name.is_empty() {
+			return None;
+		}
+		let nutriment = |key: &str| self.nutriments.get(key).copied().filter(|v| v.is_finite() && *v >= 0.0);
+		let calories = nutriment("energy-kcal_100g").or_else(|| nutriment("energy_100g").and_then(|kj| convert_unit(kj, "kJ", "kcal")))?;
+
+		let mut nutrition = Nutrients {
+			calories,
+			fats: nutriment("fat_100g").unwrap_or(0.0),
+			carbohydrates: nutriment("carbohydrates_100g").unwrap_or(0.0),
+			proteins: nutriment("proteins_100g").unwrap_or(0.0),
+			..Nutrients::default()
+		};
+		for (idx, value) in nutrition.optional_mut().iter_mut().enumerate() {
+			**value = nutriment(OPTIONAL_NUTRIMENTS[idx]).and_then(|grams| convert_unit(grams, "g", OPTIONAL_NUTRIENTS[idx].1));
+		}
+		if nutrition.sodium.is_none() {
+			nutrition.sodium = nutriment("salt_100g").and_then(|salt| convert_unit(salt * SODIUM_PER_SALT, "g", "mg"));
+		}
+
+		// "1 cup (30 g)" is a portion.  "30 g" on its own is just the serving.
+		let serving_grams = self.serving_quantity.filter(|g| *g > 0.0);
+		let measure = self.serving_size.split('(').next().unwrap_or("").trim();
+		let is_mass = |measure: &str| matches!(measure.parse::<FoodQuantity>().map(|q| q.canonical()), Ok(FoodQuantity::Mass(_)));
+		let portions: Vec<Portion> = serving_grams.filter(|_| !measure.is_empty() && !is_mass(measure))
+			.and_then(|grams| Portion::from_measure(measure, grams))
+			.into_iter().collect();
+
+		let categories = self.categories.iter().map(|c| c.strip_prefix("en:").unwrap_or(c).to_string());
+		let allergens = self.allergens.iter().map(|a| format!("allergen:{}", a.strip_prefix("en:").unwrap_or(a)));
+		let manufacturer = if self.brand_owner.is_empty() { self.brands.split(',').next().unwrap_or("").trim() } else { &self.brand_owner };
+
+		Some(Food {
+			source: format!("off:{}", self.code),
+			name: self.name.clone(),
+			manufacturer: manufacturer.to_string(),
+			tags: categories.chain(allergens).map(|t| t.replace('|', "/")).collect::<Vec<String>>().join("|"),
+			nutrition,
+			barcodes: normalize_barcode(&self.code).into_iter().collect(), // Stores' in-house codes often don't validate.
+			volume_of_100g: volume_of_100g_from_portions(&portions).unwrap_or(0.0),
+			servings_in_100g: serving_grams.map(|g| 100.0 / g).unwrap_or(0.0),
+			user_defined: false,
+			portions,
+			..Food::default()
+		})
+	}
+}
+
+// The dump mixes numbers and numeric strings.
+fn json_number(value: &Value) -> Option<f32> {
+	match value {
+		Value::Number(n) => n.as_f64().map(|n| n as f32),
+		Value::String(s) => s.trim().parse().ok(),
+		_ => None,
+	}
+}
+
+/// Products from the JSONL dump, one line at a time.  Yields an error for a line that isn't JSON.
+pub struct JsonlProducts<R: BufRead> {
+	lines: Lines<R>,
+	line: u64,
+	filter: Filter,
+}
+
+pub fn read_jsonl<R: BufRead>(reader: R, filter: Filter) -> JsonlProducts<R> {
+	JsonlProducts { lines: reader.lines(), line: 0, filter }
+}
+
+impl<R: BufRead> Iterator for JsonlProducts<R> {
+	type Item = Result<Food>;
+
+	fn next(&mut self) -> Option<Result<Food>> {
+		for text in &mut self.lines {
+			self.line += 1;
+			let text = match text {
+				Ok(text) => text,
+				Err(e) => { return Some(Err(e.into())); },
+			};
+			if text.trim().is_empty() {
+				continue;
+			}
+			let product = match serde_json::from_str::<Value>(&text) {
+				Ok(value) => Product::from_json(&value),
+				Err(e) => { return Some(Err(Error::Import { line: self.line, message: e.to_string() })); },
+			};
+			if !self.filter.accepts(&product) {
+				continue;
+			}
+			if let Some(food) = product.to_food() {
+				return Some(Ok(food));
+			}
+		}
+		None
+	}
+}
+
+/// Products from the tab-separated CSV export, one row at a time.
+pub struct CsvProducts<R: Read> {
+	columns: CsvColumns,
+	records: csv::StringRecordsIntoIter<R>,
+	filter: Filter,
+}
+
+pub fn read_csv<R: Read>(reader: R, filter: Filter) -> Result<CsvProducts<R>> {
+	// Fields aren't quoted, and product names are full of stray quote marks.
+	let mut reader = csv::ReaderBuilder::new().delimiter(b'\t').quoting(false).flexible(true).from_reader(reader);
+	let columns = CsvColumns::new(reader.headers()?);
+	Ok(CsvProducts { columns, records: reader.into_records(), filter })
+}
+
+impl<R: Read> Iterator for CsvProducts<R> {
+	type Item = Result<Food>;
+
+	fn next(&mut self) -> Option<Result<Food>> {
+		for record in &mut self.records {
+			let record = match record {
+				Ok(record) => record,
+				Err(e) => { return Some(Err(e.into())); },
+			};
+			let product = Product::from_csv(&self.columns, &record);
+			if !self.filter.accepts(&product) {
+				continue;
+			}
+			if let Some(food) = product.to_food() {
+				return Some(Ok(food));
+			}
+		}
+		None
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use crate::import::openfoodfacts::{self, Filter};
+
+	const SAMPLE_JSONL: &str = include_str!("../../tests/fixtures/openfoodfacts_sample.jsonl");
+	const SAMPLE_CSV: &str = include_str!("../../tests/fixtures/openfoodfacts_sample.csv");
+
+	fn all_jsonl(filter: Filter) -> Vec<Food> {
+		openfoodfacts::read_jsonl(SAMPLE_JSONL.as_bytes(), filter).collect::<Result<Vec<Food>>>().unwrap()
+	}
+
+	#[test]
+	fn jsonl_products() {
+		// The sample has four products.  One has no nutrition facts, so it's skipped.
+		let foods = all_jsonl(Filter::default());
+		assert_eq!(foods.len(), 3);
+
+		let nutella = &foods[0];
+		assert_eq!(nutella.name, "Nutella");
+		assert_eq!(nutella.manufacturer, "Ferrero");
+		assert_eq!(nutella.barcodes, vec!["3017620422003".to_string()]);
+		assert_eq!(nutella.source, "off:3017620422003");
+		assert_eq!(nutella.tags, "spreads|sweet-spreads|hazelnut-spreads|allergen:milk|allergen:nuts|allergen:soybeans");
+		assert!((nutella.nutrition.calories - 539.0).abs() < 1e-3);
+		assert!((nutella.nutrition.sodium.unwrap() - 42.8).abs() < 1e-3); // 0.107g salt.
+		assert!((nutella.servings_in_100g - 100.0 / 15.0).abs() < 1e-4);
+		assert!(nutella.portions.is_empty()); // "15 g" is just the serving.
+
+		let cereal = &foods[1];
+		assert_eq!(cereal.manufacturer, "Kellogg's");
+		assert!((cereal.nutrition.calories - 378.3).abs() < 0.1); // From kJ.
+		assert!((cereal.nutrition.iron.unwrap() - 8.0).abs() < 1e-3); // Grams in the dump.
+		assert!((cereal.nutrition.vitamin_d.unwrap() - 4.2).abs() < 1e-3);
+		assert_eq!(cereal.portions, vec![Portion { name: "cup".to_string(), grams: 30.0 }]);
+		assert!(cereal.barcodes.is_empty()); // Bad check digit.
+		assert_eq!(cereal.source, "off:5053827154089");
+		assert!(cereal.has_density());
+	}
+
+	#[test]
+	fn filters() {
+		let french = all_jsonl(Filter { countries: vec!["France".to_string()], ..Filter::default() });
+		assert_eq!(french.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>(), vec!["Nutella", "Evian"]);
+
+		let uk = all_jsonl(Filter { countries: vec!["en:united-kingdom".to_string(), "United States".to_string()], ..Filter::default() });
+		assert_eq!(uk.len(), 2);
+
+		let complete = all_jsonl(Filter { min_completeness: 0.8, ..Filter::default() });
+		assert_eq!(complete.len(), 1);
+
+		let broken = "{\"code\": \"1\"}\nnot json\n";
+		let results: Vec<Result<Food>> = openfoodfacts::read_jsonl(broken.as_bytes(), Filter::default()).collect();
+		assert!(matches!(results.as_slice(), [Err(Error::Import { line: 2, .. })]));
+	}
+
+	#[test]
+	fn csv_products() {
+		let foods = openfoodfacts::read_csv(SAMPLE_CSV.as_bytes(), Filter { countries: vec!["united-states".to_string()], ..Filter::default() }).unwrap()
+			.collect::<Result<Vec<Food>>>().unwrap();
+		assert_eq!(foods.len(), 1);
+		let cereal = &foods[0];
+		assert_eq!(cereal.name, "Corn Flakes \"Original\"");
+		assert_eq!(cereal.barcodes, vec!["0038000001109".to_string()]);
+		assert_eq!(cereal.tags, "breakfast-cereals|allergen:gluten");
+		assert!((cereal.nutrition.calories - 357.0).abs() < 1e-3);
+		assert!((cereal.nutrition.sodium.unwrap() - 500.0).abs() < 1e-3);
+
+		let mut db = FoodDB::new();
+		assert_eq!(db.import_foods(foods).added, 1);
+		assert_eq!(db.get_autocomplete_suggestions("corn".to_string()).len(), 1);
+	}
+}
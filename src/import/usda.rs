@@ -0,0 +1,426 @@
+// USDA FoodData Central downloads (fdc.nal.usda.gov/download-datasets.html): Foundation, SR Legacy, FNDDS and
+// Branded foods, either as the single JSON file or the directory of CSV tables.  Nutrients are per 100g, except
+// Branded liquids, which are per 100ml and converted here using an estimated density.
+
+use hashbrown::HashMap;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::barcode::normalize_barcode;
+use crate::density::DensityCategory;
+use crate::error::{Error, Result};
+use crate::food::{Food, Portion};
+use crate::import::{fdc_source, line_of, volume_of_100g_from_portions, CsvColumns};
+use crate::nutrition::{convert_unit, Nutrients, OPTIONAL_NUTRIENTS};
+
+// FDC nutrient IDs for the required fields, best first.  Energy falls back to the Atwater factors, then kJ.
+const CALORIES: &[u32] = &[1008, 2047, 2048, 1062];
+const FAT: &[u32] = &[1004, 1085];
+const CARBOHYDRATE: &[u32] = &[1005, 1050];
+const PROTEIN: &[u32] = &[1003];
+
+// FDC nutrient IDs for each entry of OPTIONAL_NUTRIENTS, in the same order.
+const OPTIONAL_NUTRIENT_IDS: [&[u32]; 24] = [
+	&[1079], // Fiber, total dietary
+	&[2000, 1063], // Sugars, total
+	&[1235], // Sugars, added
+	&[1258], // Fatty acids, total saturated
+	&[1257], // Fatty acids, total trans
+	&[1253], // Cholesterol
+	&[1093], // Sodium, Na
+	&[1092], // Potassium, K
+	&[1087], // Calcium, Ca
+	&[1089], // Iron, Fe
+	&[1090], // Magnesium, Mg
+	&[1091], // Phosphorus, P
+	&[1095], // Zinc, Zn
+	&[1106], // Vitamin A, RAE
+	&[1162], // Vitamin C, total ascorbic acid
+	&[1114], // Vitamin D (D2 + D3)
+	&[1109], // Vitamin E (alpha-tocopherol)
+	&[1185], // Vitamin K (phylloquinone)
+	&[1165], // Thiamin
+	&[1166], // Riboflavin
+	&[1167], // Niacin
+	&[1175], // Vitamin B-6
+	&[1190], // Folate, DFE
+	&[1178], // Vitamin B-12
+];
+
+// Sub-samples and acquisition records in the Foundation CSVs are lab paperwork, not foods.
+const FOOD_DATA_TYPES: [&str; 4] = ["foundation_food", "sr_legacy_food", "survey_fndds_food", "branded_food"];
+
+// measure_unit.csv's name for "see the modifier instead".
+const UNDETERMINED_UNIT: &str = "undetermined";
+
+// One food as the JSON download spells it.  The CSV reader builds the same thing from its tables.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct FdcFood {
+	fdc_id: u64,
+	description: String,
+	#[serde(default)]
+	food_nutrients: Vec<FdcFoodNutrient>,
+	#[serde(default)]
+	food_portions: Vec<FdcPortion>,
+	#[serde(default)]
+	food_category: Option<FdcCategory>,
+	#[serde(default)]
+	branded_food_category: Option<String>,
+	#[serde(default)]
+	brand_owner: Option<String>,
+	#[serde(default)]
+	brand_name: Option<String>,
+	#[serde(default)]
+	gtin_upc: Option<String>,
+	#[serde(default)]
+	serving_size: Option<f32>,
+	#[serde(default)]
+	serving_size_unit: Option<String>,
+	#[serde(default)]
+	household_serving_full_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FdcCategory {
+	description: String,
+}
+
+#[derive(Deserialize)]
+struct FdcFoodNutrient {
+	nutrient: FdcNutrient,
+	#[serde(default)]
+	amount: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FdcNutrient {
+	id: u32,
+	#[serde(default)]
+	unit_name: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct FdcPortion {
+	#[serde(default)]
+	gram_weight: Option<f32>,
+	#[serde(default)]
+	amount: Option<f32>,
+	#[serde(default)]
+	modifier: Option<String>,
+	#[serde(default)]
+	portion_description: Option<String>,
+	#[serde(default)]
+	measure_unit: Option<FdcMeasureUnit>,
+}
+
+#[derive(Deserialize)]
+struct FdcMeasureUnit {
+	name: String,
+}
+
+/// Read a JSON download, e.g. FoodData_Central_foundation_food_json_2024-04-18.json.  The whole file is parsed at
+/// once, so the Branded download needs several GB of memory; its CSV tables stream instead.
+pub fn read_json<R: Read>(reader: R) -> Result<Vec<Food>> {
+	// {"FoundationFoods": [...]}, {"SRLegacyFoods": [...]}, {"BrandedFoods": [...]}, and so on.
+	let download: HashMap<String, Vec<FdcFood>> = serde_json::from_reader(io::BufReader::new(reader))?;
+	Ok(download.values().flatten().map(FdcFood::to_food).collect())
+}
+
+/// Read an unzipped CSV download.  food.csv and food_nutrient.csv are required.  nutrient.csv, food_portion.csv,
+/// measure_unit.csv, food_category.csv and branded_food.csv are used when present.
+pub fn read_csv_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Food>> {
+	let dir = dir.as_ref();
+	let categories = read_id_to_text(dir, "food_category.csv", "description")?;
+	let units = read_id_to_text(dir, "nutrient.csv", "unit_name")?;
+	let measure_units = read_id_to_text(dir, "measure_unit.csv", "name")?;
+
+	let mut foods: Vec<FdcFood> = vec![];
+	let mut positions: HashMap<u64, usize> = HashMap::new();
+	let (columns, mut records) = open_table(dir, "food.csv")?.ok_or_else(|| missing_table(dir, "food.csv"))?;
+	for record in records.records() {
+		let record = record?;
+		if columns.text(&record, "data_type").is_some_and(|t| !FOOD_DATA_TYPES.contains(&t)) {
+			continue;
+		}
+		let fdc_id = required_id(&columns, &record, "fdc_id")?;
+		positions.insert(fdc_id, foods.len());
+		foods.push(FdcFood {
+			fdc_id,
+			description: columns.text(&record, "description").unwrap_or_default().to_string(),
+			food_category: columns.text(&record, "food_category_id").and_then(|id| categories.get(id)).map(|c| FdcCategory { description: c.clone() }),
+			..FdcFood::default()
+		});
+	}
+
+	let (columns, mut records) = open_table(dir, "food_nutrient.csv")?.ok_or_else(|| missing_table(dir, "food_nutrient.csv"))?;
+	for record in records.records() {
+		let record = record?;
+		let food = match positions.get(&required_id(&columns, &record, "fdc_id")?) {
+			Some(&position) => &mut foods[position],
+			None => continue,
+		};
+		let id = required_id(&columns, &record, "nutrient_id")? as u32;
+		food.food_nutrients.push(FdcFoodNutrient {
+			nutrient: FdcNutrient { id, unit_name: units.get(&id.to_string()).cloned().unwrap_or_default() },
+			amount: columns.number(&record, "amount")?,
+		});
+	}
+
+	if let Some((columns, mut records)) = open_table(dir, "food_portion.csv")? {
+		for record in records.records() {
+			let record = record?;
+			if let Some(&position) = positions.get(&required_id(&columns, &record, "fdc_id")?) {
+				foods[position].food_portions.push(FdcPortion {
+					gram_weight: columns.number(&record, "gram_weight")?,
+					amount: columns.number(&record, "amount")?,
+					modifier: columns.text(&record, "modifier").map(|m| m.to_string()),
+					portion_description: columns.text(&record, "portion_description").map(|d| d.to_string()),
+					measure_unit: columns.text(&record, "measure_unit_id").and_then(|id| measure_units.get(id)).map(|name| FdcMeasureUnit { name: name.clone() }),
+				});
+			}
+		}
+	}
+
+	if let Some((columns, mut records)) = open_table(dir, "branded_food.csv")? {
+		for record in records.records() {
+			let record = record?;
+			if let Some(&position) = positions.get(&required_id(&columns, &record, "fdc_id")?) {
+				let food = &mut foods[position];
+				let text = |column: &str| columns.text(&record, column).map(|t| t.to_string());
+				food.brand_owner = text("brand_owner");
+				food.brand_name = text("brand_name");
+				food.gtin_upc = text("gtin_upc");
+				food.branded_food_category = text("branded_food_category");
+				food.serving_size = columns.number(&record, "serving_size")?;
+				food.serving_size_unit = text("serving_size_unit");
+				food.household_serving_full_text = text("household_serving_fulltext");
+			}
+		}
+	}
+
+	Ok(foods.iter().map(FdcFood::to_food).collect())
+}
+
+fn open_table(dir: &Path, name: &str) -> Result<Option<(CsvColumns, csv::Reader<File>)>> {
+	let file = match File::open(dir.join(name)) {
+		Ok(file) => file,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(None); },
+		Err(e) => { return Err(e.into()); },
+	};
+	let mut reader = csv::Reader::from_reader(file);
+	let columns = CsvColumns::new(reader.headers()?);
+	Ok(Some((columns, reader)))
+}
+
+fn missing_table(dir: &Path, name: &str) -> Error {
+	Error::Import { line: 0, message: format!("{} has no {}", dir.display(), name) }
+}
+
+// Small lookup tables keyed by their 'id' column.  Empty if the file isn't there.
+fn read_id_to_text(dir: &Path, name: &str, column: &str) -> Result<HashMap<String, String>> {
+	let mut table = HashMap::new();
+	if let Some((columns, mut records)) = open_table(dir, name)? {
+		for record in records.records() {
+			let record = record?;
+			if let (Some(id), Some(text)) = (columns.text(&record, "id"), columns.text(&record, column)) {
+				table.insert(id.to_string(), text.to_string());
+			}
+		}
+	}
+	Ok(table)
+}
+
+fn required_id(columns: &CsvColumns, record: &csv::StringRecord, column: &str) -> Result<u64> {
+	columns.text(record, column).and_then(|id| id.parse().ok()).ok_or_else(|| Error::Import {
+		line: line_of(record),
+		message: format!("missing or invalid {}", column),
+	})
+}
+
+impl FdcPortion {
+	// SR Legacy puts the unit in the modifier ("cup, chopped") and leaves the measure unit undetermined.  Foundation
+	// foods split them ("cup" + "chopped").  FNDDS just has a description like "1 cup".
+	fn to_portion(&self) -> Option<Portion> {
+		let grams = self.gram_weight.filter(|g| *g > 0.0)?;
+		let count = self.amount.filter(|a| *a > 0.0).unwrap_or(1.0);
+		let unit = self.measure_unit.as_ref().map(|u| u.name.trim()).filter(|u| !u.is_empty() && *u != UNDETERMINED_UNIT);
+		let modifier = self.modifier.as_deref().map(str::trim).filter(|m| !m.is_empty());
+		let name = match (unit, modifier) {
+			(Some(unit), Some(modifier)) => format!("{}, {}", unit, modifier),
+			(Some(unit), None) => unit.to_string(),
+			(None, Some(modifier)) => modifier.to_string(),
+			(None, None) => { return self.portion_description.as_deref().and_then(|d| Portion::from_measure(d, grams)); },
+		};
+		Some(Portion { name, grams: grams / count })
+	}
+}
+
+impl FdcFood {
+	fn nutrient(&self, ids: &[u32], unit: &str) -> Option<f32> {
+		ids.iter().find_map(|id| {
+			let entry = self.food_nutrients.iter().find(|n| n.nutrient.id == *id)?;
+			let amount = entry.amount?;
+			// The CSV tables may come without nutrient.csv.  Our IDs are all in the unit we expect.
+			if entry.nutrient.unit_name.is_empty() {
+				return Some(amount);
+			}
+			convert_unit(amount, &entry.nutrient.unit_name, unit)
+		})
+	}
+
+	fn to_food(&self) -> Food {
+		let mut nutrition = Nutrients {
+			calories: self.nutrient(CALORIES, "kcal").unwrap_or(0.0),
+			fats: self.nutrient(FAT, "g").unwrap_or(0.0),
+			carbohydrates: self.nutrient(CARBOHYDRATE, "g").unwrap_or(0.0),
+			proteins: self.nutrient(PROTEIN, "g").unwrap_or(0.0),
+			..Nutrients::default()
+		};
+		for (idx, value) in nutrition.optional_mut().iter_mut().enumerate() {
+			**value = self.nutrient(OPTIONAL_NUTRIENT_IDS[idx], OPTIONAL_NUTRIENTS[idx].1);
+		}
+
+		let mut food = Food {
+			id: self.fdc_id,
+			source: fdc_source(self.fdc_id),
+			name: self.description.trim().to_string(),
+			manufacturer: self.brand_owner.clone().or_else(|| self.brand_name.clone()).unwrap_or_default(),
+			tags: self.branded_food_category.clone().or_else(|| self.food_category.as_ref().map(|c| c.description.clone())).unwrap_or_default().replace('|', "/"),
+			nutrition,
+			barcodes: self.gtin_upc.iter().filter_map(|code| normalize_barcode(code).ok()).collect(),
+			user_defined: false,
+			portions: self.food_portions.iter().filter_map(FdcPortion::to_portion).collect(),
+			..Food::default()
+		};
+
+		// Branded foods have one serving, measured in grams or (for drinks) millilitres.  Drinks also give their
+		// nutrients per 100ml, so they are converted with a density guessed from the food and flagged as estimated.
+		let serving_size = self.serving_size.filter(|s| *s > 0.0);
+		let serving_unit = self.serving_size_unit.as_deref().unwrap_or("").to_lowercase();
+		let mut grams_per_ml = 1.0;
+		if ["ml", "mlt"].contains(&serving_unit.as_str()) {
+			let category = match DensityCategory::of_food(&food) {
+				Some(DensityCategory::Oil) => DensityCategory::Oil,
+				_ => DensityCategory::Liquid,
+			};
+			grams_per_ml = category.grams_per_ml();
+			food.nutrition = food.nutrition / grams_per_ml;
+			food.volume_of_100g = category.volume_of_100g();
+			food.volume_estimated = true;
+		}
+		if let Some(size) = serving_size {
+			let grams = size * grams_per_ml;
+			food.servings_in_100g = 100.0 / grams;
+			if let Some(portion) = self.household_serving_full_text.as_deref().and_then(|text| Portion::from_measure(text, grams)) {
+				food.portions.push(portion);
+			}
+		}
+		if !food.volume_estimated {
+			if let Some(volume) = volume_of_100g_from_portions(&food.portions) {
+				food.volume_of_100g = volume;
+			}
+		}
+		food
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use crate::import::usda;
+
+	const SAMPLE_JSON: &str = include_str!("../../tests/fixtures/fdc_sample.json");
+	const SAMPLE_CSV_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fdc_csv");
+
+	fn by_source<'a>(foods: &'a [Food], source: &str) -> &'a Food {
+		foods.iter().find(|f| f.source == source).unwrap_or_else(|| panic!("No food from {}", source))
+	}
+
+	#[test]
+	fn json_download() {
+		let foods = usda::read_json(SAMPLE_JSON.as_bytes()).unwrap();
+		assert_eq!(foods.len(), 3);
+
+		let milk = by_source(&foods, "fdc:746782");
+		assert_eq!(milk.name, "Milk, whole, 3.25% milkfat, with added vitamin D");
+		assert_eq!(milk.tags, "Dairy and Egg Products");
+		assert_eq!(milk.nutrition.proteins, 3.27);
+		assert!((milk.nutrition.calories - 60.0).abs() < 1e-3); // Only Atwater energy was given.
+		assert_eq!(milk.nutrition.calcium, Some(123.0));
+		assert!((milk.nutrition.vitamin_d.unwrap() - 1.1).abs() < 1e-4); // Reported in µg.
+		assert_eq!(milk.portions, vec![Portion { name: "cup".to_string(), grams: 249.0 }]);
+		assert!((milk.volume_of_100g - 95.02).abs() < 0.01);
+
+		let spinach = by_source(&foods, "fdc:168462");
+		assert_eq!(spinach.portions[0], Portion { name: "cup".to_string(), grams: 30.0 });
+		assert_eq!(spinach.portions[1], Portion { name: "bunch".to_string(), grams: 340.0 });
+		assert!((spinach.nutrition.sodium.unwrap() - 79.0).abs() < 1e-4);
+		assert_eq!(spinach.nutrition.vitamin_a, Some(469.0));
+
+		let cereal = by_source(&foods, "fdc:2117388");
+		assert_eq!(cereal.manufacturer, "Kellogg Company US");
+		assert_eq!(cereal.tags, "Cereal");
+		assert_eq!(cereal.barcodes, vec!["0038000001277".to_string()]);
+		assert!((cereal.nutrition.calories - 357.0).abs() < 1e-3);
+		assert!((cereal.nutrition.sodium.unwrap() - 500.0).abs() < 1e-3);
+		assert!((cereal.servings_in_100g - 100.0 / 28.0).abs() < 1e-4);
+		assert!((cereal.portions[0].grams - 28.0 / 0.75).abs() < 1e-3);
+		assert!(!cereal.volume_estimated);
+	}
+
+	#[test]
+	fn csv_download() {
+		let foods = usda::read_csv_dir(SAMPLE_CSV_DIR).unwrap();
+		assert_eq!(foods.len(), 2); // The sub-sample row is skipped.
+
+		let milk = by_source(&foods, "fdc:746782");
+		assert_eq!(milk.tags, "Dairy and Egg Products");
+		assert!((milk.nutrition.calories - 60.0).abs() < 1e-3);
+		assert!((milk.nutrition.vitamin_d.unwrap() - 1.1).abs() < 1e-4);
+		assert_eq!(milk.portions, vec![Portion { name: "cup".to_string(), grams: 249.0 }]);
+
+		let juice = by_source(&foods, "fdc:2345678");
+		assert_eq!(juice.manufacturer, "Tropicana Products, Inc.");
+		assert_eq!(juice.barcodes, vec!["0048500202746".to_string()]);
+		// Labelled per 100ml, stored per 100g using a typical drink's density.
+		assert!(juice.volume_estimated);
+		assert_eq!(juice.volume_of_100g, DensityCategory::Liquid.volume_of_100g());
+		assert!((juice.nutrition.calories - 46.0 / 1.03).abs() < 1e-3);
+		assert!((juice.servings_in_100g - 100.0 / (240.0 * 1.03)).abs() < 1e-4);
+		assert!((juice.portions.last().unwrap().grams - 240.0 * 1.03).abs() < 1e-3);
+
+		assert!(matches!(usda::read_csv_dir("does/not/exist"), Err(Error::Import { .. })));
+	}
+
+	#[test]
+	fn reimporting_updates_in_place() {
+		let mut db = FoodDB::new();
+		let mine = {
+			let f = db.new_food();
+			f.name = "Milk, whole".to_string();
+			f.id
+		};
+		let summary = db.import_foods(usda::read_json(SAMPLE_JSON.as_bytes()).unwrap());
+		assert_eq!(summary, import::ImportSummary { added: 3, updated: 0 });
+		let milk = db.foods.iter().find(|f| f.source == "fdc:746782").unwrap().id;
+		let breakfast = db.new_meal();
+		db.add_food_to_meal(breakfast, milk, FoodQuantity::Cups(1.0)).unwrap();
+
+		// Next year's release: the milk got richer, and the cereal got a new FDC ID but kept its barcode.
+		let newer = SAMPLE_JSON.replace("\"amount\": 3.27", "\"amount\": 3.4").replace("2117388", "2299999");
+		let summary = db.import_foods(usda::read_json(newer.as_bytes()).unwrap());
+		assert_eq!(summary, import::ImportSummary { added: 0, updated: 3 });
+		assert_eq!(db.foods.len(), 4);
+		assert_eq!(db.food(milk).unwrap().nutrition.proteins, 3.4);
+		assert_eq!(db.food(mine).unwrap().source, "");
+		assert!(db.foods.iter().any(|f| f.source == "fdc:2299999"));
+		assert!(db.recompute_all_meal_nutrients().is_ok());
+		assert_eq!(db.find_by_barcode("038000001277").unwrap().len(), 1);
+	}
+}
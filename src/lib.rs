@@ -1,35 +1,66 @@
 
-use bzip2::{Compression, read::{BzEncoder, BzDecoder}};
-use hashbrown::HashMap;
+use bzip2::read::{BzEncoder, BzDecoder};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::{BufReader, Read, Result, prelude::*};
+use std::io::{BufReader, Read, prelude::*};
 
+mod barcode;
+mod density;
+mod diary;
+mod error;
 mod food;
+pub mod import;
+mod ids;
 mod meal;
+mod migration;
 mod nutrition;
+mod query;
+mod recipe;
 mod search;
+mod usage;
 
-pub use food::{Food, FoodID, FoodQuantity};
+pub use barcode::{normalize_barcode, normalize_ean_8, normalize_upc_e};
+pub use density::DensityCategory;
+pub use error::{Error, Result};
+pub use food::{Food, FoodID, FoodQuantity, Portion, QuantityParseError, QuantityParseErrorKind};
 pub use meal::{Meal, MealID};
+pub use nutrition::{EnergyUnit, Nutrients, KILOJOULES_PER_KILOCALORIE, OPTIONAL_NUTRIENTS};
+pub use query::{Basis, Comparison, FoodQuery, Nutrient, SortOrder};
+pub use recipe::CookingMethod;
+pub use search::FoodSearchResult;
+pub use usage::SearchContext;
+pub use migration::CURRENT_SCHEMA_VERSION;
+pub use bzip2::Compression;
 use search::*;
-use chrono::Datelike;
-use bzip2::Decompress;
+use ids::IdIndex;
+use chrono::{Datelike, DateTime, Utc};
+
+// Every bzip2 stream starts with 'BZh' followed by the block size, '1' through '9'.
+const BZIP2_MAGIC: &[u8] = b"BZh";
 
 #[derive(Serialize, Deserialize)]
 pub struct FoodDB {
+	schema_version: u32,
 	foods: Vec<Food>,
 	meals: Vec<Meal>,
+	food_ids: IdIndex<FoodID>,
+	meal_ids: IdIndex<MealID>,
 	#[serde(skip)]
 	food_index: SearchIndex,
+	#[serde(skip)]
+	nutrient_index: Option<query::NutrientIndex>,
 }
 
 impl Default for FoodDB {
 	fn default() -> Self {
 		FoodDB {
+			schema_version: CURRENT_SCHEMA_VERSION,
 			foods: vec![],
 			meals: vec![],
+			food_ids: IdIndex::new(),
+			meal_ids: IdIndex::new(),
 			food_index: SearchIndex::empty(),
+			nutrient_index: None,
 		}
 	}
 }
@@ -42,22 +73,51 @@ impl FoodDB {
 	pub fn open(filename: &str) -> Result<Self> {
 		let fin = File::open(filename)?;
 		let mut reader = BufReader::new(fin);
-		let mut json_buffer = String::new();
-		reader.read_to_string(&mut json_buffer)?;
-		Self::from_string(&json_buffer)
+		let mut json_buffer = vec![];
+		reader.read_to_end(&mut json_buffer)?;
+		Self::from_string(utf8_or_parse_error(&json_buffer)?)
 	}
 
+	/// Parse a database of any known schema version, migrating older documents up to CURRENT_SCHEMA_VERSION.
 	pub fn from_string(json_string:&str) -> Result<Self> {
-		let mut deserialized: FoodDB = serde_json::from_str(json_string)?;
-		deserialized.food_index.reindex(&deserialized.foods);
+		let probe: migration::VersionProbe = serde_json::from_str(json_string)?;
+		migration::check_supported(probe.schema_version)?;
+		let mut deserialized: FoodDB = if probe.schema_version == CURRENT_SCHEMA_VERSION {
+			// Going straight from the text keeps line/column info in errors.
+			serde_json::from_str(json_string)?
+		} else {
+			let mut doc: serde_json::Value = serde_json::from_str(json_string)?;
+			migration::migrate(&mut doc)?;
+			serde_json::from_value(doc)?
+		};
+		deserialized.rebuild_id_lookups()?;
+		deserialized.food_index = SearchIndex::new(&deserialized.foods);
+		deserialized.food_index.reindex_usage(&deserialized.meals);
 		Ok(deserialized)
 	}
 
+	/// Open either a plain JSON or a bzip2-compressed database, sniffing the format from the first bytes of the file.
+	pub fn open_any(filename: &str) -> Result<Self> {
+		let mut fin = File::open(filename)?;
+		let mut blob = vec![];
+		fin.read_to_end(&mut blob)?;
+		Self::from_bytes(&blob)
+	}
+
+	/// Like open_any, but for a buffer already in memory (e.g., from include_bytes!).
+	pub fn from_bytes(blob:&[u8]) -> Result<Self> {
+		if is_bz2(blob) {
+			Self::from_bz2(blob)
+		} else {
+			Self::from_string(utf8_or_parse_error(blob)?)
+		}
+	}
+
 	pub fn from_bz2(blob:&[u8]) -> Result<Self> {
-		let mut buffer = String::new();
+		let mut buffer = vec![];
 		let mut decompressor = BzDecoder::new(blob);
-		decompressor.read_to_string(&mut buffer);
-		Self::from_string(&buffer)
+		decompressor.read_to_end(&mut buffer).map_err(Error::Decompression)?;
+		Self::from_string(utf8_or_parse_error(&buffer)?)
 	}
 
 	pub fn save(&self, filename:&str) -> Result<()> {
@@ -68,12 +128,24 @@ impl FoodDB {
 		Ok(())
 	}
 
-	pub fn save_compressed(&self, filename:&str) -> Result<()> {
-		todo!()
+	/// Write the database as bzip2-compressed JSON.  Compression::best() is a good choice for things we ship.
+	pub fn save_compressed(&self, filename:&str, level:Compression) -> Result<()> {
+		let compressed = self.to_bz2(level)?;
+		let mut fout = File::create(filename)?;
+		fout.write_all(&compressed)?;
+		Ok(())
+	}
+
+	pub fn to_bz2(&self, level:Compression) -> Result<Vec<u8>> {
+		let serialized = serde_json::to_vec(self)?;
+		let mut compressor = BzEncoder::new(serialized.as_slice(), level);
+		let mut compressed = vec![];
+		compressor.read_to_end(&mut compressed)?;
+		Ok(compressed)
 	}
 
 	pub fn new_meal(&mut self) -> MealID {
-		let next_meal_id = self.meals.len();
+		let next_meal_id = self.meal_ids.allocate(self.meals.len());
 		let meal = Meal {
 			id: next_meal_id,
 			..Meal::default()
@@ -83,72 +155,210 @@ impl FoodDB {
 	}
 
 	pub fn new_food(&mut self) -> &mut Food {
-		let next_food_id = self.foods.len();
+		let position = self.foods.len();
 		let food = Food {
-			id: next_food_id as u64,
+			id: self.food_ids.allocate(position),
 			user_defined: true,
 			..Food::default()
 		};
 		self.foods.push(food);
-		self.food_index.reindex(&self.foods);
-		self.foods.get_mut(next_food_id).expect("Unable to fetch newly added food reference.  Out of memory?")
+		self.reindex_foods();
+		// The caller fills the food in after we return.
+		self.nutrients_changed();
+		self.foods.get_mut(position).expect("Unable to fetch newly added food reference.  Out of memory?")
 	}
 
-	pub fn add_food_to_meal(&mut self, meal: MealID, food: FoodID, quantity: FoodQuantity) -> bool {
-		let food_ref = self.get_food_from_id(food);
+	// For a food that is already filled in, so only it needs indexing.  Any ID it carries is replaced.
+	pub(crate) fn push_food(&mut self, food: Food) -> FoodID {
+		let food = Food {
+			id: self.food_ids.allocate(self.foods.len()),
+			user_defined: true,
+			..food
+		};
+		self.food_index.add_food(&food);
+		self.nutrients_changed();
+		let id = food.id;
+		self.foods.push(food);
+		id
+	}
 
-		if food_ref.is_none() {
-			return false;
+	/// Add foods that already carry their own IDs, e.g. from an importer.  Nothing is added if any ID is taken.
+	pub fn add_foods<I: IntoIterator<Item=Food>>(&mut self, foods: I) -> Result<usize> {
+		let before = self.foods.len();
+		self.foods.extend(foods);
+		if let Err(e) = self.rebuild_id_lookups() {
+			self.foods.truncate(before);
+			self.rebuild_id_lookups()?;
+			return Err(e);
 		}
+		self.reindex_foods();
+		Ok(self.foods.len() - before)
+	}
 
-		let nutrition = food_ref.unwrap().get_nutrition(quantity);
+	/// Delete a food that nothing refers to any more.  Foods still logged in a meal or used as an ingredient are kept.
+	pub fn delete_food(&mut self, food_id: FoodID) -> Result<Food> {
+		self.food(food_id)?;
+		if let Some(m) = self.meals.iter().find(|m| m.foods.iter().any(|(f, _)| *f == food_id)) {
+			return Err(Error::Validation(format!("food {} is still logged in meal {}", food_id, m.id)));
+		}
+		if let Some(f) = self.foods.iter().find(|f| f.ingredients.iter().any(|(i, _)| *i == food_id)) {
+			return Err(Error::Validation(format!("food {} is still an ingredient of {}", food_id, f.id)));
+		}
+		let position = self.food_ids.position(food_id).expect("Food lookup succeeded without a position.");
+		let removed = self.foods.remove(position);
+		self.rebuild_id_lookups()?;
+		self.reindex_foods();
+		Ok(removed)
+	}
 
-		let mut meal_ref = self.get_meal_mut_from_id(meal);
+	pub fn add_food_to_meal(&mut self, meal: MealID, food: FoodID, quantity: FoodQuantity) -> Result<()> {
+		validate_quantity(&quantity)?;
+		let nutrition = self.resolve_nutrition(food, quantity.clone())?;
 
 		// If we can't find the food or meal, abort.
-		if let Some(m) = meal_ref {
-			m.nutrients.calories += nutrition.calories;
-			m.nutrients.proteins += nutrition.proteins;
-			m.nutrients.carbohydrates += nutrition.carbohydrates;
-			m.nutrients.fats += nutrition.fats;
-			m.foods.push((food, quantity));
-			return true;
-		} else {
-			return false;
+		let m = self.get_meal_mut_from_id(meal).ok_or(Error::UnknownMeal(meal))?;
+		m.nutrients += nutrition;
+		m.foods.push((food, quantity));
+		let (time, meal_name) = (m.time, m.meal_name.clone());
+		self.food_index.record_use(food, time, &meal_name);
+		Ok(())
+	}
+
+	/// Remove the entry at `entry` (its position in Meal.foods) and return it.  The meal's totals are rebuilt.
+	pub fn remove_food_from_meal(&mut self, meal: MealID, entry: usize) -> Result<(FoodID, FoodQuantity)> {
+		// Work out the new totals first, so a failure leaves the meal as it was.
+		let mut foods = self.meal(meal)?.foods.clone();
+		if entry >= foods.len() {
+			return Err(Error::Validation(format!("meal {} has no food entry {}", meal, entry)));
 		}
+		let removed = foods.remove(entry);
+		let total = self.total_nutrients(&foods)?;
+
+		let m = self.meal_mut(meal)?;
+		m.foods = foods;
+		m.nutrients = total;
+		let (time, meal_name) = (m.time, m.meal_name.clone());
+		self.food_index.forget_use(removed.0, time, &meal_name);
+		Ok(removed)
 	}
 
-	pub fn get_food_from_id(&self, food_id:FoodID) -> Option<&Food> {
-		// food_id should be the position in the array.
-		let opt_food = self.foods.get(food_id as usize);
-		if let Some(f) = opt_food {
-			if f.id != food_id {
-				eprintln!("ERROR: food {} not found at ID index.  {} != {}", f.name, f.id, food_id);
-			}
+	pub fn set_food_quantity_in_meal(&mut self, meal: MealID, entry: usize, quantity: FoodQuantity) -> Result<()> {
+		validate_quantity(&quantity)?;
+		let mut foods = self.meal(meal)?.foods.clone();
+		match foods.get_mut(entry) {
+			Some((_, q)) => { *q = quantity; },
+			None => { return Err(Error::Validation(format!("meal {} has no food entry {}", meal, entry))); }
 		}
+		let total = self.total_nutrients(&foods)?;
 
-		opt_food
+		let m = self.meal_mut(meal)?;
+		m.foods = foods;
+		m.nutrients = total;
+		Ok(())
 	}
 
-	pub fn get_food_mut_from_id(&mut self, food_id:FoodID) -> Option<&mut Food> {
-		// food_id should be the position in the array.
-		match self.foods.get_mut(food_id as usize) {
-			Some(f) => {
-				if f.id != food_id {
-					panic!("ERROR: food {} not found at ID index.  {} != {}", f.name, f.id, food_id);
-				}
-				Some(f)
-			},
-			None => None
+	/// Move a meal to another time.  Prefer this to setting Meal.time, which leaves the search's usage counts behind.
+	pub fn set_meal_time(&mut self, meal: MealID, time: DateTime<Utc>) -> Result<()> {
+		let meal_name = self.meal(meal)?.meal_name.clone();
+		self.relog_meal(meal, time, meal_name)
+	}
+
+	/// Change a meal's name (Breakfast, Lunch...).  Prefer this to setting Meal.meal_name, for the same reason.
+	pub fn rename_meal(&mut self, meal: MealID, meal_name: &str) -> Result<()> {
+		let time = self.meal(meal)?.time;
+		self.relog_meal(meal, time, meal_name.to_string())
+	}
+
+	// Usage was recorded under the old time and name, so move it along with them.
+	fn relog_meal(&mut self, meal: MealID, time: DateTime<Utc>, meal_name: String) -> Result<()> {
+		let m = self.meal_mut(meal)?;
+		let old_time = std::mem::replace(&mut m.time, time);
+		let old_meal_name = std::mem::replace(&mut m.meal_name, meal_name.clone());
+		let foods: Vec<FoodID> = m.foods.iter().map(|(food, _)| *food).collect();
+		for food in foods {
+			self.food_index.forget_use(food, old_time, &old_meal_name);
+			self.food_index.record_use(food, time, &meal_name);
+		}
+		Ok(())
+	}
+
+	/// Delete a meal and return it.  Other meals keep their IDs.
+	pub fn delete_meal(&mut self, meal: MealID) -> Result<Meal> {
+		let position = self.meal_ids.position(meal).ok_or(Error::UnknownMeal(meal))?;
+		let removed = self.meals.remove(position);
+		self.rebuild_id_lookups()?;
+		for (food, _) in &removed.foods {
+			self.food_index.forget_use(*food, removed.time, &removed.meal_name);
+		}
+		Ok(removed)
+	}
+
+	/// Rebuild a meal's cached totals from its food entries and the current Food records.
+	pub fn recompute_meal_nutrients(&mut self, meal: MealID) -> Result<()> {
+		let total = self.total_nutrients(&self.meal(meal)?.foods)?;
+		self.meal_mut(meal)?.nutrients = total;
+		Ok(())
+	}
+
+	// What a list of meal entries adds up to.
+	fn total_nutrients(&self, foods: &[(FoodID, FoodQuantity)]) -> Result<Nutrients> {
+		Ok(foods.iter()
+			.map(|(food, quantity)| self.resolve_nutrition(*food, quantity.clone()))
+			.collect::<Result<Vec<Nutrients>>>()?
+			.into_iter()
+			.sum())
+	}
+
+	/// Call after editing a Food so that every meal which logged it reflects the change.
+	pub fn recompute_all_meal_nutrients(&mut self) -> Result<()> {
+		let meal_ids: Vec<MealID> = self.meals.iter().map(|m| m.id).collect();
+		for meal in meal_ids {
+			self.recompute_meal_nutrients(meal)?;
+		}
+		Ok(())
+	}
+
+	/// Fetch a food, distinguishing between a missing food and a corrupt index.
+	pub fn food(&self, food_id:FoodID) -> Result<&Food> {
+		let f = self.food_ids.position(food_id).and_then(|p| self.foods.get(p)).ok_or(Error::UnknownFood(food_id))?;
+		// Food.id is public, so someone may have changed it behind our back.
+		if f.id != food_id {
+			return Err(Error::IdMismatch { expected: food_id, found: f.id });
+		}
+		Ok(f)
+	}
+
+	pub fn food_mut(&mut self, food_id:FoodID) -> Result<&mut Food> {
+		self.nutrients_changed();
+		let f = self.food_ids.position(food_id).and_then(move |p| self.foods.get_mut(p)).ok_or(Error::UnknownFood(food_id))?;
+		if f.id != food_id {
+			return Err(Error::IdMismatch { expected: food_id, found: f.id });
 		}
+		Ok(f)
+	}
+
+	pub fn meal(&self, meal_id:MealID) -> Result<&Meal> {
+		self.meal_ids.position(meal_id).and_then(|p| self.meals.get(p)).ok_or(Error::UnknownMeal(meal_id))
+	}
+
+	pub fn meal_mut(&mut self, meal_id:MealID) -> Result<&mut Meal> {
+		self.meal_ids.position(meal_id).and_then(move |p| self.meals.get_mut(p)).ok_or(Error::UnknownMeal(meal_id))
+	}
+
+	pub fn get_food_from_id(&self, food_id:FoodID) -> Option<&Food> {
+		self.food(food_id).ok()
+	}
+
+	pub fn get_food_mut_from_id(&mut self, food_id:FoodID) -> Option<&mut Food> {
+		self.food_mut(food_id).ok()
 	}
 
 	pub fn get_meal_from_id(&self, meal_id:MealID) -> Option<&Meal> {
-		self.meals.get(meal_id)
+		self.meal(meal_id).ok()
 	}
 
 	pub fn get_meal_mut_from_id(&mut self, meal_id:MealID) -> Option<&mut Meal> {
-		self.meals.get_mut(meal_id)
+		self.meal_mut(meal_id).ok()
 	}
 
 	pub fn get_meals_from_date(&self, year:i32, month:u32, day:u32) -> Vec<MealID> {
@@ -166,40 +376,303 @@ impl FoodDB {
 		self.food_index.search(&food_name, None).iter().map(|fsr|{ (fsr.id, fsr.name.clone()) }).collect()
 	}
 
+	/// Foods matching a search, best first, each with a relevance from 0 to 1.  Every word must appear in the name,
+	/// manufacturer or tags; `brand:`, `name:` and `tag:` limit a word to one of them.  Typos in names are forgiven.
+	pub fn search_foods(&self, query:&str, max_results:Option<u8>) -> Vec<FoodSearchResult> {
+		self.food_index.search(query, max_results)
+	}
+
+	/// Like search_foods, but also favouring foods usually logged at this time of day or under this meal name.
+	pub fn search_foods_in_context(&self, query:&str, max_results:Option<u8>, context:&SearchContext) -> Vec<FoodSearchResult> {
+		self.food_index.search_in_context(query, max_results, context)
+	}
+
+	/// Foods with this barcode, in any format normalize_barcode accepts.  An 8 digit code that could be UPC-E or EAN-8
+	/// finds foods under either.  Fails if the code itself is invalid.
+	pub fn find_by_barcode(&self, code:&str) -> Result<Vec<FoodID>> {
+		let mut found: Vec<FoodID> = vec![];
+		for reading in barcode::barcode_readings(code)? {
+			for id in self.food_index.find_barcode(&reading) {
+				if !found.contains(id) {
+					found.push(*id);
+				}
+			}
+		}
+		Ok(found)
+	}
+
+	/// Validate a barcode and attach it to a food.  Adding one it already has does nothing.
+	pub fn add_barcode(&mut self, food_id:FoodID, code:&str) -> Result<()> {
+		let normalized = normalize_barcode(code)?;
+		let food = self.food_mut(food_id)?;
+		if !food.barcodes.contains(&normalized) {
+			food.barcodes.push(normalized.clone());
+			self.food_index.add_barcode(normalized, food_id);
+		}
+		Ok(())
+	}
+
+	/// Rebuild the search index, including the logging history behind search ranking.  Call after changing a
+	/// meal's time or name directly rather than through set_meal_time or rename_meal.
 	pub fn reindex(&mut self) {
+		self.reindex_foods();
+		self.food_index.reindex_usage(&self.meals);
+	}
+
+	// After foods are added, removed or changed.  Meals don't come into it.
+	fn reindex_foods(&mut self) {
 		self.food_index.reindex(&self.foods);
+		if self.nutrient_index.is_some() {
+			self.build_nutrient_index();
+		}
+	}
+
+	// Before handing out a food that may be edited.  query_foods stops trusting the nutrient columns until reindex.
+	fn nutrients_changed(&mut self) {
+		if let Some(index) = &mut self.nutrient_index {
+			index.stale = true;
+		}
+	}
+
+	fn rebuild_id_lookups(&mut self) -> Result<()> {
+		self.food_ids.rebuild(self.foods.iter().map(|f| f.id)).map_err(|id| Error::Validation(format!("more than one food has ID {}", id)))?;
+		self.meal_ids.rebuild(self.meals.iter().map(|m| m.id)).map_err(|id| Error::Validation(format!("more than one meal has ID {}", id)))?;
+		Ok(())
+	}
+}
+
+fn is_bz2(blob:&[u8]) -> bool {
+	blob.len() > BZIP2_MAGIC.len() && blob.starts_with(BZIP2_MAGIC) && (b'1'..=b'9').contains(&blob[BZIP2_MAGIC.len()])
+}
+
+// Report bad UTF-8 the same way serde_json reports bad JSON, so callers can point at the broken spot.
+fn utf8_or_parse_error(blob:&[u8]) -> Result<&str> {
+	std::str::from_utf8(blob).map_err(|e| {
+		let valid = &blob[..e.valid_up_to()];
+		let line_start = valid.iter().rposition(|&b| b == b'\n').map(|p| p + 1).unwrap_or(0);
+		Error::Parse {
+			line: 1 + valid.iter().filter(|&&b| b == b'\n').count(),
+			column: 1 + valid.len() - line_start,
+			message: e.to_string(),
+		}
+	})
+}
+
+fn validate_quantity(quantity:&FoodQuantity) -> Result<()> {
+	let amount = quantity.amount();
+	if !amount.is_finite() || amount < 0.0 {
+		return Err(Error::Validation(format!("food quantity must be a non-negative number, not {}", amount)));
 	}
+	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 	use crate::*;
+	use chrono::TimeZone;
+
+	// A directory of its own for one test's files, removed again when the test finishes.
+	struct TempDir(std::path::PathBuf);
+
+	impl TempDir {
+		fn new(test_name: &str) -> Self {
+			let dir = std::env::temp_dir().join(format!("fooddb-{}-{}", test_name, std::process::id()));
+			std::fs::create_dir_all(&dir).unwrap();
+			TempDir(dir)
+		}
+
+		fn file(&self, name: &str) -> String {
+			self.0.join(name).to_str().expect("Temporary paths should be UTF-8.").to_string()
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
 
 	#[test]
 	fn make_empty_food_db() {
+		let dir = TempDir::new("make_empty_food_db");
 		let mut db = FoodDB::new();
-		let new_food_id = db.new_food();
-		//println!("New food id: {}", new_food_id);
-		db.save("empty.fdb");
-		let db2 = FoodDB::open("empty.fdb").unwrap();
+		db.new_food();
+		db.save(&dir.file("empty.fdb")).unwrap();
+		FoodDB::open(&dir.file("empty.fdb")).unwrap();
 	}
 
 	#[test]
 	fn make_single_entry_food_db() {
+		let dir = TempDir::new("make_single_entry_food_db");
 		let mut db = FoodDB::new();
 		{
-			let mut food = db.new_food();
+			let food = db.new_food();
 			food.name = "Tasty Food".to_string();
 			food.nutrition.proteins = 20.0f32;
 			food.nutrition.fats = 20.0f32;
 			food.nutrition.carbohydrates = 60.0f32;
-			food.nutrition.calories = 9 * 20 + 4 * 20; // 9 calories per gram of fat.  4 per gram of carbs.
+			food.nutrition.calories = 9.0 * 20.0 + 4.0 * 20.0; // 9 calories per gram of fat.  4 per gram of carbs.
 		}
 		db.reindex();
-		db.save("single_food.fdb");
-		let mut db2 = FoodDB::open("single_food.fdb").unwrap();
+		db.save(&dir.file("single_food.fdb")).unwrap();
+		let mut db2 = FoodDB::open(&dir.file("single_food.fdb")).unwrap();
 		assert_eq!(db2.foods.len(), 1);
 		db2.reindex();
 		assert_eq!(db2.get_autocomplete_suggestions("Tasty".to_string()).len(), 1);
 	}
+
+	#[test]
+	fn compressed_round_trip() {
+		let dir = TempDir::new("compressed_round_trip");
+		let mut db = FoodDB::new();
+		db.new_food().name = "Compressible Food".to_string();
+		db.save_compressed(&dir.file("compressed_food.fdb.bz2"), Compression::best()).unwrap();
+		db.save(&dir.file("uncompressed_food.fdb")).unwrap();
+
+		let from_bz2 = FoodDB::open_any(&dir.file("compressed_food.fdb.bz2")).unwrap();
+		let from_json = FoodDB::open_any(&dir.file("uncompressed_food.fdb")).unwrap();
+		assert_eq!(from_bz2.foods.len(), 1);
+		assert_eq!(from_bz2.foods[0].name, from_json.foods[0].name);
+		assert_eq!(from_bz2.get_autocomplete_suggestions("Compress".to_string()).len(), 1);
+
+		// Garbage isn't mistaken for a bzip2 stream.
+		assert!(!is_bz2(b"BZ"));
+		assert!(!is_bz2(b"BZhx{}"));
+		assert!(matches!(FoodDB::from_bz2(b"BZh9 not really compressed"), Err(Error::Decompression(_))));
+	}
+
+	#[test]
+	fn errors_say_what_went_wrong() {
+		match FoodDB::from_string("{\"foods\": [],\n \"meals\": oops}") {
+			Err(Error::Parse { line, column, .. }) => {
+				assert_eq!(line, 2);
+				assert_eq!(column, 11);
+			},
+			_ => panic!("Expected a parse error."),
+		}
+		match FoodDB::from_bytes(b"{\"foods\": [],\n\"meals\": [\xff]}") {
+			Err(Error::Parse { line, column, .. }) => {
+				assert_eq!(line, 2);
+				assert_eq!(column, 11);
+			},
+			_ => panic!("Expected a parse error for invalid UTF-8."),
+		}
+		assert!(matches!(FoodDB::open_any("does_not_exist.fdb"), Err(Error::Io(_))));
+
+		let mut db = FoodDB::new();
+		let food_id = db.new_food().id;
+		let meal_id = db.new_meal();
+		assert!(matches!(db.add_food_to_meal(meal_id, food_id + 1, FoodQuantity::Mass(10.0)), Err(Error::UnknownFood(_))));
+		assert!(matches!(db.add_food_to_meal(meal_id + 1, food_id, FoodQuantity::Mass(10.0)), Err(Error::UnknownMeal(_))));
+		assert!(matches!(db.add_food_to_meal(meal_id, food_id, FoodQuantity::Serving(-1.0)), Err(Error::Validation(_))));
+		assert!(matches!(db.add_food_to_meal(meal_id, food_id, FoodQuantity::Portion("slice".to_string(), 1.0)), Err(Error::MissingConversion { .. })));
+		// New foods have no density or serving size, so volumes and servings can't be logged yet.
+		assert!(matches!(db.add_food_to_meal(meal_id, food_id, FoodQuantity::Cups(1.0)), Err(Error::MissingConversion { .. })));
+		assert!(matches!(db.add_food_to_meal(meal_id, food_id, FoodQuantity::Serving(1.0)), Err(Error::MissingConversion { .. })));
+		assert!(db.add_food_to_meal(meal_id, food_id, FoodQuantity::Mass(10.0)).is_ok());
+		assert_eq!(db.meal(meal_id).unwrap().foods.len(), 1);
+
+		db.foods[0].id = 7;
+		assert!(matches!(db.recompute_meal_nutrients(meal_id), Err(Error::IdMismatch { .. })));
+		assert!(matches!(db.food(food_id), Err(Error::IdMismatch { expected: 0, found: 7 })));
+	}
+
+	#[test]
+	fn edit_and_delete_meals() {
+		let mut db = FoodDB::new();
+		let bread = {
+			let f = db.new_food();
+			f.name = "Bread".to_string();
+			f.nutrition.calories = 250.0;
+			f.nutrition.carbohydrates = 50.0;
+			f.nutrition.fiber = Some(4.0);
+			f.id
+		};
+		let butter = {
+			let f = db.new_food();
+			f.name = "Butter".to_string();
+			f.nutrition.calories = 700.0;
+			f.nutrition.fats = 80.0;
+			f.id
+		};
+		let breakfast = db.new_meal();
+		let lunch = db.new_meal();
+		let dinner = db.new_meal();
+		db.add_food_to_meal(breakfast, bread, FoodQuantity::Mass(100.0)).unwrap();
+		db.add_food_to_meal(breakfast, butter, FoodQuantity::Mass(10.0)).unwrap();
+		db.add_food_to_meal(dinner, butter, FoodQuantity::Mass(20.0)).unwrap();
+		assert_eq!(db.meal(breakfast).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 250 + 70);
+
+		// Oops, that was 50g of bread.
+		db.set_food_quantity_in_meal(breakfast, 0, FoodQuantity::Mass(50.0)).unwrap();
+		assert_eq!(db.meal(breakfast).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 125 + 70);
+
+		// And no butter.
+		assert_eq!(db.remove_food_from_meal(breakfast, 1).unwrap().0, butter);
+		let totals = &db.meal(breakfast).unwrap().nutrients;
+		assert_eq!(totals.rounded_energy(EnergyUnit::Kilocalories), 125);
+		assert_eq!(totals.fats, 0.0);
+		assert_eq!(totals.fiber, Some(2.0));
+		assert_eq!(totals.sodium, None);
+		assert!(matches!(db.remove_food_from_meal(breakfast, 1), Err(Error::Validation(_))));
+
+		// Butter got healthier.  Everything that logged it should follow.
+		db.food_mut(butter).unwrap().nutrition.calories = 600.0;
+		db.recompute_all_meal_nutrients().unwrap();
+		assert_eq!(db.meal(dinner).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 120);
+
+		// Skipped lunch.
+		db.delete_meal(lunch).unwrap();
+		assert_eq!(db.meals.len(), 2);
+		assert!(matches!(db.meal(lunch), Err(Error::UnknownMeal(_))));
+		assert!(matches!(db.delete_meal(lunch), Err(Error::UnknownMeal(_))));
+		assert_eq!(db.meal(dinner).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 120);
+
+		// Edits that can't be totalled leave the meal alone.  Here a food record got out of step.
+		db.add_food_to_meal(dinner, bread, FoodQuantity::Mass(100.0)).unwrap();
+		db.foods[0].id = 7;
+		assert!(matches!(db.remove_food_from_meal(dinner, 0), Err(Error::IdMismatch { .. })));
+		assert!(matches!(db.set_food_quantity_in_meal(dinner, 1, FoodQuantity::Mass(5.0)), Err(Error::IdMismatch { .. })));
+		assert_eq!(db.meal(dinner).unwrap().foods.len(), 2);
+		assert_eq!(db.meal(dinner).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 120 + 250);
+		db.foods[0].id = bread;
+
+		// Times and names can change after foods are logged.
+		let evening = Utc.with_ymd_and_hms(2021, 3, 20, 19, 0, 0).unwrap();
+		db.set_meal_time(dinner, evening).unwrap();
+		db.rename_meal(dinner, "Supper").unwrap();
+		assert_eq!(db.meal(dinner).unwrap().time, evening);
+		assert_eq!(db.meal(dinner).unwrap().meal_name, "Supper");
+		assert!(matches!(db.rename_meal(lunch, "Supper"), Err(Error::UnknownMeal(_))));
+	}
+
+	#[test]
+	fn ids_survive_deletion() {
+		let dir = TempDir::new("ids_survive_deletion");
+		let mut db = FoodDB::new();
+		let first = db.new_food().id;
+		let second = db.new_food().id;
+		let third = db.new_food().id;
+		db.new_food().name = "Survivor".to_string();
+		let meal = db.new_meal();
+		db.add_food_to_meal(meal, third, FoodQuantity::Mass(10.0)).unwrap();
+		db.food_mut(second).unwrap().ingredients.push((third, FoodQuantity::Mass(5.0)));
+
+		// In use, so can't be removed.
+		assert!(matches!(db.delete_food(third), Err(Error::Validation(_))));
+
+		db.delete_food(first).unwrap();
+		assert!(matches!(db.food(first), Err(Error::UnknownFood(_))));
+		assert_eq!(db.food(third).unwrap().id, third);
+		assert_eq!(db.food(second).unwrap().ingredients[0].0, third);
+		assert_eq!(db.get_autocomplete_suggestions("Surv".to_string()).len(), 1);
+
+		// The deleted ID is never handed out again, even after a round trip through disk.
+		db.save(&dir.file("ids_survive_deletion.fdb")).unwrap();
+		let mut db = FoodDB::open(&dir.file("ids_survive_deletion.fdb")).unwrap();
+		let newest = db.new_food().id;
+		assert!(newest > third);
+		assert_ne!(newest, first);
+		assert!(db.recompute_meal_nutrients(meal).is_ok());
+	}
 }
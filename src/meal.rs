@@ -14,6 +14,7 @@ pub struct Meal {
 	pub name: String,
 	pub tags: String,
 
+	// Change these with FoodDB::set_meal_time and rename_meal once foods are logged, so search usage keeps up.
 	pub time: DateTime<Utc>,
 	pub meal_name: String, // Breakfast, Lunch, Dinner, etc.
 
@@ -35,7 +36,7 @@ impl Default for Meal {
 
 			time: Utc::now(),
 
-			nutrients: Nutrients::default(),
+			nutrients: Nutrients::zero(),
 
 			foods: vec![],
 		}
@@ -0,0 +1,203 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Bump this and append a step to MIGRATIONS whenever the serialized shape of Food, Meal, or Nutrients changes.
+pub const CURRENT_SCHEMA_VERSION: u32 = 10;
+
+// Files written before we tracked versions (including the starter DBs from the old Python importer) have no schema_version.
+const UNVERSIONED_SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+// MIGRATIONS[n] upgrades a document from version n+1 to version n+2.
+const MIGRATIONS: [Migration; (CURRENT_SCHEMA_VERSION - UNVERSIONED_SCHEMA_VERSION) as usize] = [
+	v1_to_v2,
+	v2_to_v3,
+	v3_to_v4,
+	v4_to_v5,
+	v5_to_v6,
+	v6_to_v7,
+	v7_to_v8,
+	v8_to_v9,
+	v9_to_v10,
+];
+
+// Lets us check the version without building the whole document tree.
+#[derive(Deserialize)]
+pub(crate) struct VersionProbe {
+	#[serde(default = "unversioned")]
+	pub schema_version: u32,
+}
+
+fn unversioned() -> u32 {
+	UNVERSIONED_SCHEMA_VERSION
+}
+
+/// Upgrade a document in place, one version at a time, until it matches CURRENT_SCHEMA_VERSION.
+pub(crate) fn migrate(doc: &mut Value) -> Result<()> {
+	let version = match doc.get("schema_version") {
+		None => UNVERSIONED_SCHEMA_VERSION,
+		Some(v) => v.as_u64().map(|v| v as u32).ok_or_else(|| Error::Validation(format!("schema_version must be a positive integer, not {}", v)))?,
+	};
+	check_supported(version)?;
+
+	for step in &MIGRATIONS[(version - UNVERSIONED_SCHEMA_VERSION) as usize..] {
+		step(doc)?;
+	}
+	doc["schema_version"] = Value::from(CURRENT_SCHEMA_VERSION);
+	Ok(())
+}
+
+pub(crate) fn check_supported(version: u32) -> Result<()> {
+	if version < UNVERSIONED_SCHEMA_VERSION {
+		Err(Error::Validation(format!("unknown schema version {}", version)))
+	} else if version > CURRENT_SCHEMA_VERSION {
+		Err(Error::Validation(format!("file uses schema version {}, but this build only understands up to version {}", version, CURRENT_SCHEMA_VERSION)))
+	} else {
+		Ok(())
+	}
+}
+
+fn root_object(doc: &mut Value) -> Result<&mut serde_json::Map<String, Value>> {
+	doc.as_object_mut().ok_or_else(|| Error::Validation("food database must be a JSON object".to_string()))
+}
+
+// v1 -> v2: Introduces schema_version.  Nothing else changed shape, but make sure both lists exist so later steps can rely on them.
+fn v1_to_v2(doc: &mut Value) -> Result<()> {
+	let root = root_object(doc)?;
+	root.entry("foods").or_insert_with(|| Value::Array(vec![]));
+	root.entry("meals").or_insert_with(|| Value::Array(vec![]));
+	Ok(())
+}
+
+// v2 -> v3: IDs stop being Vec positions.  Store counters that start past every ID already in the file.
+fn v2_to_v3(doc: &mut Value) -> Result<()> {
+	let root = root_object(doc)?;
+	for (list, counter) in [("foods", "food_ids"), ("meals", "meal_ids")].iter() {
+		let next_id = root[*list].as_array()
+			.map(|items| items.iter().filter_map(|item| item["id"].as_u64()).map(|id| id + 1).max().unwrap_or(0))
+			.unwrap_or(0);
+		root.insert(counter.to_string(), serde_json::json!({ "next_id": next_id }));
+	}
+	Ok(())
+}
+
+// v3 -> v4: Recipes can record their cooked mass.  Nothing had one before.
+fn v3_to_v4(doc: &mut Value) -> Result<()> {
+	for food in foods_mut(doc)? {
+		food["cooked_mass"] = Value::Null;
+	}
+	Ok(())
+}
+
+// v4 -> v5: Nutrients gained optional fiber, sugars, sodium, vitamins, etc.  Absent means unknown, so old files are
+// already valid.  The bump stops older builds from silently dropping the new values.
+fn v4_to_v5(_doc: &mut Value) -> Result<()> {
+	Ok(())
+}
+
+// v5 -> v6: Calories became fractional.  Whole numbers still parse, but older builds can't read our fractions.
+fn v5_to_v6(_doc: &mut Value) -> Result<()> {
+	Ok(())
+}
+
+// v6 -> v7: FoodQuantity gained imperial and kitchen units, and grams became fractional.  Existing quantities still parse.
+fn v6_to_v7(_doc: &mut Value) -> Result<()> {
+	Ok(())
+}
+
+// v7 -> v8: Foods carry named portions, and quantities can refer to them.  Nothing had any before.
+fn v7_to_v8(doc: &mut Value) -> Result<()> {
+	for food in foods_mut(doc)? {
+		food["portions"] = Value::Array(vec![]);
+	}
+	Ok(())
+}
+
+// v8 -> v9: Densities can be estimated from a food's category.  Everything on disk so far was entered or imported.
+fn v8_to_v9(doc: &mut Value) -> Result<()> {
+	for food in foods_mut(doc)? {
+		food["volume_estimated"] = Value::Bool(false);
+	}
+	Ok(())
+}
+
+// v9 -> v10: Imported foods remember where they came from, and packaged foods carry barcodes.
+fn v9_to_v10(doc: &mut Value) -> Result<()> {
+	for food in foods_mut(doc)? {
+		food["barcodes"] = Value::Array(vec![]);
+		food["source"] = Value::from("");
+	}
+	Ok(())
+}
+
+fn foods_mut(doc: &mut Value) -> Result<impl Iterator<Item=&mut Value>> {
+	let foods = root_object(doc)?.get_mut("foods").and_then(|f| f.as_array_mut()).ok_or_else(|| Error::Validation("'foods' must be a list".to_string()))?;
+	Ok(foods.iter_mut())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use crate::migration::CURRENT_SCHEMA_VERSION;
+
+	// One fixture per historical schema version.  Each describes the same diary.
+	const FIXTURES: [(u32, &str); 10] = [
+		(1, include_str!("../tests/fixtures/schema_v1.fdb")),
+		(2, include_str!("../tests/fixtures/schema_v2.fdb")),
+		(3, include_str!("../tests/fixtures/schema_v3.fdb")),
+		(4, include_str!("../tests/fixtures/schema_v4.fdb")),
+		(5, include_str!("../tests/fixtures/schema_v5.fdb")),
+		(6, include_str!("../tests/fixtures/schema_v6.fdb")),
+		(7, include_str!("../tests/fixtures/schema_v7.fdb")),
+		(8, include_str!("../tests/fixtures/schema_v8.fdb")),
+		(9, include_str!("../tests/fixtures/schema_v9.fdb")),
+		(10, include_str!("../tests/fixtures/schema_v10.fdb")),
+	];
+
+	#[test]
+	fn every_schema_version_has_a_fixture() {
+		assert_eq!(FIXTURES.len() as u32, CURRENT_SCHEMA_VERSION);
+		for (idx, (version, _)) in FIXTURES.iter().enumerate() {
+			assert_eq!(*version, idx as u32 + 1);
+		}
+	}
+
+	#[test]
+	fn fixtures_migrate_to_current() {
+		for (version, fixture) in FIXTURES.iter() {
+			let db = FoodDB::from_string(fixture).unwrap_or_else(|e| panic!("Failed to load schema v{} fixture: {}", version, e));
+			assert_eq!(db.foods.len(), 2, "v{}", version);
+			assert_eq!(db.get_food_from_id(1).unwrap().name, "Milk, whole", "v{}", version);
+			assert_eq!(db.get_autocomplete_suggestions("oat".to_string()).len(), 1, "v{}", version);
+
+			let breakfast = db.get_meal_from_id(0).unwrap();
+			assert_eq!(breakfast.meal_name, "Breakfast", "v{}", version);
+			assert_eq!(breakfast.foods.len(), 2, "v{}", version);
+			assert_eq!(db.get_meals_from_date(2021, 7, 4), vec![0], "v{}", version);
+
+			// Saving always writes the current version.
+			let resaved = serde_json::to_value(&db).unwrap();
+			assert_eq!(resaved["schema_version"], CURRENT_SCHEMA_VERSION, "v{}", version);
+			assert_eq!(resaved["food_ids"]["next_id"], 2, "v{}", version);
+			assert_eq!(resaved["meal_ids"]["next_id"], 1, "v{}", version);
+		}
+	}
+
+	#[test]
+	fn unversioned_file_without_meals() {
+		let db = FoodDB::from_string("{\"foods\": []}").unwrap();
+		assert!(db.foods.is_empty());
+		assert!(db.meals.is_empty());
+	}
+
+	#[test]
+	fn reject_newer_schema() {
+		let from_the_future = format!("{{\"schema_version\": {}, \"foods\": [], \"meals\": []}}", CURRENT_SCHEMA_VERSION + 1);
+		assert!(matches!(FoodDB::from_string(&from_the_future), Err(Error::Validation(_))));
+		assert!(matches!(FoodDB::from_string("{\"schema_version\": 0, \"foods\": [], \"meals\": []}"), Err(Error::Validation(_))));
+	}
+}
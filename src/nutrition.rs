@@ -1,22 +1,318 @@
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, Mul};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
 
-#[derive(Serialize, Deserialize, Clone)]
+// Everything past the macros is optional, because most foods only list a few of them and "unknown" is not "zero".
+// Unknown values are left out of the JSON entirely to keep the starter DB small.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Nutrients {
-	pub calories: u32,  // In units
+	pub calories: f32,  // In kilocalories.  Kept fractional so many small portions add up; round with rounded_energy for display.
 	pub fats: f32, // In grams
 	pub carbohydrates: f32, // In grams
-	pub proteins: f32 // In grams
+	pub proteins: f32, // In grams
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fiber: Option<f32>, // In grams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sugars: Option<f32>, // In grams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub added_sugars: Option<f32>, // In grams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub saturated_fats: Option<f32>, // In grams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub trans_fats: Option<f32>, // In grams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cholesterol: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sodium: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub potassium: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub calcium: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub iron: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub magnesium: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub phosphorus: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub zinc: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_a: Option<f32>, // In micrograms, retinol activity equivalents
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_c: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_d: Option<f32>, // In micrograms
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_e: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_k: Option<f32>, // In micrograms
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub thiamin: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub riboflavin: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub niacin: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_b6: Option<f32>, // In milligrams
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub folate: Option<f32>, // In micrograms, dietary folate equivalents
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub vitamin_b12: Option<f32>, // In micrograms
+}
+
+pub const KILOJOULES_PER_KILOCALORIE: f32 = 4.184;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnergyUnit {
+	Kilocalories,
+	Kilojoules,
+}
+
+impl EnergyUnit {
+	pub fn symbol(self) -> &'static str {
+		match self {
+			EnergyUnit::Kilocalories => "kcal",
+			EnergyUnit::Kilojoules => "kJ",
+		}
+	}
+
+	/// Convert an amount of energy in this unit to `target`.
+	pub fn convert(self, amount: f32, target: EnergyUnit) -> f32 {
+		match (self, target) {
+			(EnergyUnit::Kilocalories, EnergyUnit::Kilojoules) => amount * KILOJOULES_PER_KILOCALORIE,
+			(EnergyUnit::Kilojoules, EnergyUnit::Kilocalories) => amount / KILOJOULES_PER_KILOCALORIE,
+			_ => amount,
+		}
+	}
+}
+
+// Nutrient amounts are masses or energies.  IU can't be converted without knowing the compound, so it isn't.
+pub(crate) fn convert_unit(amount: f32, from: &str, to: &str) -> Option<f32> {
+	let scale = |unit: &str| match unit.to_lowercase().as_str() {
+		"g" => Some(("mass", 1.0)),
+		"mg" => Some(("mass", 1e-3)),
+		"ug" | "µg" | "mcg" => Some(("mass", 1e-6)),
+		"kcal" => Some(("energy", 1.0)),
+		"kj" => Some(("energy", 1.0 / KILOJOULES_PER_KILOCALORIE)),
+		_ => None,
+	};
+	let (from_kind, from_scale) = scale(from)?;
+	let (to_kind, to_scale) = scale(to)?;
+	if from_kind != to_kind {
+		return None;
+	}
+	Some(amount * from_scale / to_scale)
 }
 
+/// Name and unit of every optional nutrient, in the same order as Nutrients::optional and Nutrients::optional_mut.
+pub const OPTIONAL_NUTRIENTS: [(&str, &str); 24] = [
+	("fiber", "g"),
+	("sugars", "g"),
+	("added_sugars", "g"),
+	("saturated_fats", "g"),
+	("trans_fats", "g"),
+	("cholesterol", "mg"),
+	("sodium", "mg"),
+	("potassium", "mg"),
+	("calcium", "mg"),
+	("iron", "mg"),
+	("magnesium", "mg"),
+	("phosphorus", "mg"),
+	("zinc", "mg"),
+	("vitamin_a", "mcg"),
+	("vitamin_c", "mg"),
+	("vitamin_d", "mcg"),
+	("vitamin_e", "mg"),
+	("vitamin_k", "mcg"),
+	("thiamin", "mg"),
+	("riboflavin", "mg"),
+	("niacin", "mg"),
+	("vitamin_b6", "mg"),
+	("folate", "mcg"),
+	("vitamin_b12", "mcg"),
+];
+
 impl Default for Nutrients {
 	fn default() -> Self {
 		Nutrients {
-			calories: 0,
+			calories: 0f32,
 			fats: 0f32,
 			carbohydrates: 0f32,
 			proteins: 0f32,
+
+			fiber: None,
+			sugars: None,
+			added_sugars: None,
+			saturated_fats: None,
+			trans_fats: None,
+			cholesterol: None,
+			sodium: None,
+			potassium: None,
+			calcium: None,
+			iron: None,
+			magnesium: None,
+			phosphorus: None,
+			zinc: None,
+			vitamin_a: None,
+			vitamin_c: None,
+			vitamin_d: None,
+			vitamin_e: None,
+			vitamin_k: None,
+			thiamin: None,
+			riboflavin: None,
+			niacin: None,
+			vitamin_b6: None,
+			folate: None,
+			vitamin_b12: None,
+		}
+	}
+}
+
+impl Nutrients {
+	/// Nothing at all, with every optional nutrient known to be zero.  Start sums from this rather than default(),
+	/// whose optional nutrients are unknown.
+	pub fn zero() -> Self {
+		let mut n = Nutrients::default();
+		for slot in n.optional_mut().iter_mut() {
+			**slot = Some(0.0);
+		}
+		n
+	}
+
+	pub fn energy(&self, unit: EnergyUnit) -> f32 {
+		EnergyUnit::Kilocalories.convert(self.calories, unit)
+	}
+
+	pub fn set_energy(&mut self, amount: f32, unit: EnergyUnit) {
+		self.calories = unit.convert(amount, EnergyUnit::Kilocalories);
+	}
+
+	/// Energy rounded to the nearest whole unit, as printed on a label.  Don't feed this back into arithmetic.
+	pub fn rounded_energy(&self, unit: EnergyUnit) -> i64 {
+		self.energy(unit).round() as i64
+	}
+
+	/// The optional nutrients, ordered as in OPTIONAL_NUTRIENTS.
+	pub fn optional(&self) -> [Option<f32>; 24] {
+		[
+			self.fiber, self.sugars, self.added_sugars, self.saturated_fats, self.trans_fats, self.cholesterol,
+			self.sodium, self.potassium, self.calcium, self.iron, self.magnesium, self.phosphorus, self.zinc,
+			self.vitamin_a, self.vitamin_c, self.vitamin_d, self.vitamin_e, self.vitamin_k,
+			self.thiamin, self.riboflavin, self.niacin, self.vitamin_b6, self.folate, self.vitamin_b12,
+		]
+	}
+
+	pub fn optional_mut(&mut self) -> [&mut Option<f32>; 24] {
+		[
+			&mut self.fiber, &mut self.sugars, &mut self.added_sugars, &mut self.saturated_fats, &mut self.trans_fats, &mut self.cholesterol,
+			&mut self.sodium, &mut self.potassium, &mut self.calcium, &mut self.iron, &mut self.magnesium, &mut self.phosphorus, &mut self.zinc,
+			&mut self.vitamin_a, &mut self.vitamin_c, &mut self.vitamin_d, &mut self.vitamin_e, &mut self.vitamin_k,
+			&mut self.thiamin, &mut self.riboflavin, &mut self.niacin, &mut self.vitamin_b6, &mut self.folate, &mut self.vitamin_b12,
+		]
+	}
+
+	/// Look up an optional nutrient by its name in OPTIONAL_NUTRIENTS.
+	pub fn get_optional(&self, name: &str) -> Option<f32> {
+		let idx = OPTIONAL_NUTRIENTS.iter().position(|(n, _)| *n == name)?;
+		self.optional()[idx]
+	}
+}
+
+// A total is only known if every part of it is.  Skipping the unknown parts would quietly under-report.
+fn add_optional(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+	match (a, b) {
+		(Some(x), Some(y)) => Some(x + y),
+		_ => None,
+	}
+}
+
+// Same rule as add_optional: an unknown on either side leaves the difference unknown.
+fn sub_optional(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+	match (a, b) {
+		(Some(x), Some(y)) => Some(x - y),
+		_ => None,
+	}
+}
+
+// Absolute tolerance for small values, relative tolerance for large ones, so sums of many portions still compare equal.
+const ABSOLUTE_TOLERANCE: f32 = 1e-3;
+const RELATIVE_TOLERANCE: f32 = 1e-4;
+
+fn approx_eq(a: f32, b: f32) -> bool {
+	(a - b).abs() <= ABSOLUTE_TOLERANCE.max(RELATIVE_TOLERANCE * a.abs().max(b.abs()))
+}
+
+impl Nutrients {
+	// Combine two sets of nutrients field by field.
+	fn zip_with(&self, rhs: &Nutrients, op: impl Fn(f32, f32) -> f32, optional_op: impl Fn(Option<f32>, Option<f32>) -> Option<f32>) -> Nutrients {
+		let mut result = Nutrients {
+			calories: op(self.calories, rhs.calories),
+			fats: op(self.fats, rhs.fats),
+			carbohydrates: op(self.carbohydrates, rhs.carbohydrates),
+			proteins: op(self.proteins, rhs.proteins),
+			..Nutrients::default()
+		};
+		let (lhs, rhs) = (self.optional(), rhs.optional());
+		for (idx, out) in result.optional_mut().iter_mut().enumerate() {
+			**out = optional_op(lhs[idx], rhs[idx]);
+		}
+		result
+	}
+
+	fn map(&self, op: impl Fn(f32) -> f32) -> Nutrients {
+		let mut result = Nutrients {
+			calories: op(self.calories),
+			fats: op(self.fats),
+			carbohydrates: op(self.carbohydrates),
+			proteins: op(self.proteins),
+			..Nutrients::default()
+		};
+		for (out, value) in result.optional_mut().iter_mut().zip(self.optional().iter()) {
+			**out = value.map(&op);
+		}
+		result
+	}
+
+	/// Subtract, but never go below zero.  Handy for "remaining for today" once a target has been overshot.
+	pub fn saturating_sub(&self, rhs: &Nutrients) -> Nutrients {
+		self.zip_with(rhs, |a, b| (a - b).max(0.0), |a, b| sub_optional(a, b).map(|v| v.max(0.0)))
+	}
+
+	/// True if every nutrient is within `tolerance` of the other.  Unknowns only match unknowns.
+	pub fn approx_eq(&self, other: &Nutrients, tolerance: f32) -> bool {
+		let close = |a: f32, b: f32| (a - b).abs() <= tolerance;
+		close(self.calories, other.calories) && close(self.fats, other.fats) && close(self.carbohydrates, other.carbohydrates) && close(self.proteins, other.proteins)
+			&& self.optional().iter().zip(other.optional().iter()).all(|pair| match pair {
+				(Some(a), Some(b)) => close(*a, *b),
+				(None, None) => true,
+				_ => false,
+			})
+	}
+}
+
+// Float math means exact comparisons are never useful here, so equality is approximate.
+impl PartialEq for Nutrients {
+	fn eq(&self, other: &Nutrients) -> bool {
+		approx_eq(self.calories, other.calories) && approx_eq(self.fats, other.fats) && approx_eq(self.carbohydrates, other.carbohydrates) && approx_eq(self.proteins, other.proteins)
+			&& self.optional().iter().zip(other.optional().iter()).all(|pair| match pair {
+				(Some(a), Some(b)) => approx_eq(*a, *b),
+				(None, None) => true,
+				_ => false,
+			})
+	}
+}
+
+impl fmt::Display for Nutrients {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} kcal, {:.1} g fat, {:.1} g carbohydrates, {:.1} g protein", self.rounded_energy(EnergyUnit::Kilocalories), self.fats, self.carbohydrates, self.proteins)?;
+		for ((name, unit), value) in OPTIONAL_NUTRIENTS.iter().zip(self.optional().iter()) {
+			if let Some(v) = value {
+				write!(f, ", {:.1} {} {}", v, unit, name.replace('_', " "))?;
+			}
 		}
+		Ok(())
 	}
 }
 
@@ -24,12 +320,7 @@ impl Mul<f32> for Nutrients {
 	type Output = Nutrients;
 
 	fn mul(self, rhs: f32) -> Self::Output {
-		Nutrients {
-			calories: ((self.calories as f32) * rhs) as u32,
-			fats: self.fats * rhs,
-			carbohydrates: self.carbohydrates * rhs,
-			proteins: self.proteins * rhs,
-		}
+		self.map(|v| v * rhs)
 	}
 }
 
@@ -41,14 +332,204 @@ impl Mul<Nutrients> for f32 {
 	}
 }
 
+impl Div<f32> for Nutrients {
+	type Output = Nutrients;
+
+	fn div(self, rhs: f32) -> Self::Output {
+		self.map(|v| v / rhs)
+	}
+}
+
 impl Add<Nutrients> for Nutrients {
 	type Output = Nutrients;
 	fn add(self, rhs: Nutrients) -> Self::Output {
-		Nutrients {
-			calories: self.calories + rhs.calories,
-			carbohydrates: self.carbohydrates + rhs.carbohydrates,
-			fats: self.fats + rhs.fats,
-			proteins: self.proteins + rhs.proteins
+		self.zip_with(&rhs, |a, b| a + b, add_optional)
+	}
+}
+
+impl AddAssign<Nutrients> for Nutrients {
+	fn add_assign(&mut self, rhs: Nutrients) {
+		*self = self.zip_with(&rhs, |a, b| a + b, add_optional);
+	}
+}
+
+impl Sub<Nutrients> for Nutrients {
+	type Output = Nutrients;
+	fn sub(self, rhs: Nutrients) -> Self::Output {
+		self.zip_with(&rhs, |a, b| a - b, sub_optional)
+	}
+}
+
+impl SubAssign<Nutrients> for Nutrients {
+	fn sub_assign(&mut self, rhs: Nutrients) {
+		*self = self.zip_with(&rhs, |a, b| a - b, sub_optional);
+	}
+}
+
+impl Sum for Nutrients {
+	fn sum<I: Iterator<Item=Nutrients>>(iter: I) -> Self {
+		iter.fold(Nutrients::zero(), |total, n| total + n)
+	}
+}
+
+impl<'a> Sum<&'a Nutrients> for Nutrients {
+	fn sum<I: Iterator<Item=&'a Nutrients>>(iter: I) -> Self {
+		iter.fold(Nutrients::zero(), |total, n| total.zip_with(n, |a, b| a + b, add_optional))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::nutrition::*;
+
+	#[test]
+	fn unknown_is_not_zero() {
+		let apple = Nutrients {
+			calories: 52.0,
+			fiber: Some(2.4),
+			vitamin_c: Some(4.6),
+			..Nutrients::default()
+		};
+		let mystery = Nutrients {
+			calories: 100.0,
+			fiber: Some(1.0),
+			..Nutrients::default()
+		};
+
+		let two_apples = apple.clone() * 2.0;
+		assert_eq!(two_apples.fiber, Some(4.8));
+		assert_eq!(two_apples.sodium, None);
+
+		let lunch = apple + mystery;
+		assert_eq!(lunch.calories, 152.0);
+		assert!((lunch.fiber.unwrap() - 3.4).abs() < 1e-5);
+		assert_eq!(lunch.vitamin_c, None);
+		assert_eq!(lunch.sodium, None);
+		assert_eq!(lunch.get_optional("fiber"), lunch.fiber);
+		assert_eq!(lunch.get_optional("not_a_nutrient"), None);
+
+		// Sums start from zero, so a single known part stays known.
+		let snack = Nutrients::zero() + two_apples;
+		assert_eq!(snack.vitamin_c, Some(9.2));
+		assert_eq!(snack.sodium, None);
+
+		// Unknowns are left out of the file, and come back as unknown.
+		let json = serde_json::to_string(&lunch).unwrap();
+		assert!(!json.contains("sodium"));
+		let reloaded: Nutrients = serde_json::from_str(&json).unwrap();
+		assert_eq!(reloaded.sodium, None);
+		assert!((reloaded.fiber.unwrap() - 3.4).abs() < 1e-5);
+	}
+
+	#[test]
+	fn small_portions_add_up() {
+		// A 1g nibble of a 250 kcal/100g food, a thousand times over.  Truncating each one would give zero.
+		let snack = Nutrients {
+			calories: 250.0,
+			..Nutrients::default()
+		};
+		let mut total = Nutrients::default();
+		for _ in 0..1000 {
+			total += snack.clone() * 0.01;
 		}
+		assert_eq!(total.rounded_energy(EnergyUnit::Kilocalories), 2500);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn energy_units() {
+		let mut n = Nutrients::default();
+		n.set_energy(418.4, EnergyUnit::Kilojoules);
+		assert!((n.calories - 100.0).abs() < 1e-4);
+		assert!((n.energy(EnergyUnit::Kilojoules) - 418.4).abs() < 1e-3);
+		assert_eq!(n.rounded_energy(EnergyUnit::Kilocalories), 100);
+		assert_eq!(n.rounded_energy(EnergyUnit::Kilojoules), 418);
+		assert_eq!(EnergyUnit::Kilojoules.convert(1.0, EnergyUnit::Kilojoules), 1.0);
+		assert_eq!(EnergyUnit::Kilojoules.symbol(), "kJ");
+	}
+
+	#[test]
+	fn arithmetic() {
+		let oats = Nutrients {
+			calories: 150.0,
+			fats: 3.0,
+			carbohydrates: 27.0,
+			proteins: 5.0,
+			fiber: Some(4.0),
+			..Nutrients::default()
+		};
+		let milk = Nutrients {
+			calories: 90.0,
+			fats: 4.8,
+			carbohydrates: 7.0,
+			proteins: 5.0,
+			calcium: Some(180.0),
+			..Nutrients::default()
+		};
+		let target = Nutrients {
+			calories: 2000.0,
+			fats: 70.0,
+			carbohydrates: 250.0,
+			proteins: 60.0,
+			fiber: Some(30.0),
+			..Nutrients::default()
+		};
+
+		let breakfast: Nutrients = vec![oats.clone(), milk.clone()].into_iter().sum();
+		assert_eq!(breakfast, oats.clone() + milk.clone());
+		let by_ref: Nutrients = [oats.clone(), milk.clone()].iter().sum();
+		assert_eq!(breakfast, by_ref);
+		assert_eq!(breakfast.calories, 240.0);
+		assert_eq!(breakfast.fiber, None); // The milk doesn't say.
+		assert_eq!(breakfast.calcium, None); // Nor do the oats.
+		let just_oats: Nutrients = vec![oats.clone()].into_iter().sum();
+		assert_eq!(just_oats, oats);
+
+		let mut running = Nutrients::zero();
+		running += oats.clone();
+		running += milk.clone();
+		assert_eq!(running, breakfast);
+		running -= milk.clone();
+		assert!(running.approx_eq(&Nutrients { fiber: None, ..oats.clone() }, 1e-4)); // Taking the milk back out doesn't make fiber known again.
+
+		// Remaining for today.  Anything unknown on either side stays unknown.
+		let remaining = target.clone() - breakfast.clone();
+		assert_eq!(remaining.calories, 1760.0);
+		assert_eq!(remaining.fiber, None);
+		assert_eq!(remaining.calcium, None);
+		assert_eq!((target.clone() - oats.clone()).fiber, Some(26.0));
+
+		// Overshooting goes negative with plain subtraction, and stops at zero with saturating_sub.
+		let feast = oats.clone() * 20.0;
+		assert!((target.clone() - feast.clone()).calories < 0.0);
+		assert_eq!(target.saturating_sub(&feast).calories, 0.0);
+		assert_eq!(target.saturating_sub(&feast).fiber, Some(0.0));
+
+		// Average over a week.
+		let week_average = (breakfast.clone() * 7.0) / 7.0;
+		assert_eq!(week_average, breakfast);
+
+		// Tolerance.
+		let mut nudged = breakfast.clone();
+		nudged.calories += 0.0001;
+		assert_eq!(nudged, breakfast);
+		nudged.calories += 1.0;
+		assert_ne!(nudged, breakfast);
+		assert!(nudged.approx_eq(&breakfast, 1.5));
+		assert!(!oats.approx_eq(&(oats.clone() + milk.clone()), 1000.0)); // Unknown fiber on one side.
+
+		assert_eq!(format!("{}", oats), "150 kcal, 3.0 g fat, 27.0 g carbohydrates, 5.0 g protein, 4.0 g fiber");
+	}
+
+	#[test]
+	fn optional_nutrient_table_matches_fields() {
+		let mut n = Nutrients::default();
+		for (idx, slot) in n.optional_mut().iter_mut().enumerate() {
+			**slot = Some(idx as f32);
+		}
+		let json = serde_json::to_value(&n).unwrap();
+		for (idx, (name, _unit)) in OPTIONAL_NUTRIENTS.iter().enumerate() {
+			assert_eq!(json[*name].as_f64(), Some(idx as f64), "{}", name);
+		}
+	}
+}
@@ -0,0 +1,424 @@
+// Find foods by what's in them, e.g. to swap one food for another that still fits the day's macros.
+//
+//   protein per 100 kcal > 10, fat < 5 g per 100 g, tag:vegetarian, sorted by protein density, limit 20
+//
+// Clauses are separated by commas and must all hold.  Amounts are per 100 g unless they say "per 100 kcal" or
+// "density", which is the same thing.  A food that doesn't list a nutrient never passes a filter on it.
+
+use std::cmp::Ordering;
+
+use crate::error::{Error, Result};
+use crate::food::{Food, FoodID};
+use crate::nutrition::{convert_unit, Nutrients, OPTIONAL_NUTRIENTS};
+use crate::FoodDB;
+
+const MACRONUTRIENTS: [(&str, &str); 4] = [("calories", "kcal"), ("fats", "g"), ("carbohydrates", "g"), ("proteins", "g")];
+const NUTRIENT_COUNT: usize = MACRONUTRIENTS.len() + OPTIONAL_NUTRIENTS.len();
+
+/// A nutrient that can be queried: one of the macros or one of OPTIONAL_NUTRIENTS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Nutrient(usize);
+
+impl Nutrient {
+	pub const CALORIES: Nutrient = Nutrient(0);
+	pub const FATS: Nutrient = Nutrient(1);
+	pub const CARBOHYDRATES: Nutrient = Nutrient(2);
+	pub const PROTEINS: Nutrient = Nutrient(3);
+
+	/// Look a nutrient up by its field name in Nutrients, e.g. "sodium" or "vitamin_b12".  Singulars ("protein") and
+	/// spaces ("saturated fat") are fine, as are "carbs" and "energy".
+	pub fn from_name(name:&str) -> Option<Nutrient> {
+		let name = name.trim().to_lowercase().split_whitespace().collect::<Vec<&str>>().join("_");
+		let name = match name.as_str() {
+			"carbs" | "carb" => "carbohydrates".to_string(),
+			"energy" | "kcal" => "calories".to_string(),
+			_ => name,
+		};
+		(0..NUTRIENT_COUNT).map(Nutrient).find(|n| n.name() == name || n.name() == format!("{}s", name))
+	}
+
+	pub fn name(self) -> &'static str {
+		self.name_and_unit().0
+	}
+
+	/// The unit amounts of this nutrient are in: kcal, g, mg or mcg.
+	pub fn unit(self) -> &'static str {
+		self.name_and_unit().1
+	}
+
+	fn name_and_unit(self) -> (&'static str, &'static str) {
+		match self.0 {
+			i if i < MACRONUTRIENTS.len() => MACRONUTRIENTS[i],
+			i => OPTIONAL_NUTRIENTS[i - MACRONUTRIENTS.len()],
+		}
+	}
+
+	pub fn amount(self, nutrition:&Nutrients) -> Option<f32> {
+		match self.0 {
+			0 => Some(nutrition.calories),
+			1 => Some(nutrition.fats),
+			2 => Some(nutrition.carbohydrates),
+			3 => Some(nutrition.proteins),
+			i => nutrition.optional()[i - MACRONUTRIENTS.len()],
+		}
+	}
+}
+
+/// What a nutrient amount is measured against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Basis {
+	Per100Grams,
+	Per100Kilocalories, // Nutrient density.  Foods without calories have none.
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual,
+	Equal,
+}
+
+impl Comparison {
+	fn holds(self, amount:f32, limit:f32) -> bool {
+		match self {
+			Comparison::Less => amount < limit,
+			Comparison::LessOrEqual => amount <= limit,
+			Comparison::Greater => amount > limit,
+			Comparison::GreaterOrEqual => amount >= limit,
+			Comparison::Equal => (amount - limit).abs() <= 1e-3 * limit.abs().max(1.0),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+	Ascending,
+	Descending,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct NutrientFilter {
+	nutrient: Nutrient,
+	basis: Basis,
+	comparison: Comparison,
+	limit: f32, // In the nutrient's own unit.
+}
+
+/// Which foods to find and in what order.  Build one with the methods below or parse one from text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FoodQuery {
+	filters: Vec<NutrientFilter>,
+	tags: Vec<String>, // Lowercase.
+	sort: Option<(Nutrient, Basis, SortOrder)>,
+	limit: Option<usize>,
+}
+
+impl FoodQuery {
+	pub fn new() -> Self {
+		FoodQuery::default()
+	}
+
+	/// Parse the text form.  See the top of this file.
+	pub fn parse(text:&str) -> Result<FoodQuery> {
+		let mut query = FoodQuery::new();
+		for clause in text.split(',').map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()) {
+			let invalid = |reason:&str| Error::Validation(format!("can't understand '{}' in food query: {}", clause, reason));
+			if let Some(tag) = clause.strip_prefix("tag:") {
+				query = query.tag(tag.trim());
+			} else if let Some(count) = clause.strip_prefix("limit ") {
+				query = query.limit(count.trim().parse().map_err(|_| invalid("the limit should be a whole number"))?);
+			} else if let Some(measure) = ["sort by ", "sorted by ", "order by "].iter().find_map(|p| clause.strip_prefix(p)) {
+				let (measure, order) = match measure.rsplit_once(' ') {
+					Some((rest, "asc")) | Some((rest, "ascending")) => (rest, SortOrder::Ascending),
+					Some((rest, "desc")) | Some((rest, "descending")) => (rest, SortOrder::Descending),
+					_ => (measure, SortOrder::Descending),
+				};
+				let (nutrient, basis) = parse_measure(measure).map_err(|reason| invalid(&reason))?;
+				query = query.sort_by(nutrient, basis.unwrap_or(Basis::Per100Grams), order);
+			} else {
+				query.filters.push(parse_filter(&clause).map_err(|reason| invalid(&reason))?);
+			}
+		}
+		Ok(query)
+	}
+
+	/// Only foods whose amount of `nutrient`, measured against `basis`, compares to `limit` this way.
+	pub fn filter(mut self, nutrient:Nutrient, basis:Basis, comparison:Comparison, limit:f32) -> Self {
+		self.filters.push(NutrientFilter { nutrient, basis, comparison, limit });
+		self
+	}
+
+	/// Only foods with this tag.  Case is ignored.
+	pub fn tag(mut self, tag:&str) -> Self {
+		self.tags.push(tag.to_lowercase());
+		self
+	}
+
+	/// Foods that don't list the nutrient go last.
+	pub fn sort_by(mut self, nutrient:Nutrient, basis:Basis, order:SortOrder) -> Self {
+		self.sort = Some((nutrient, basis, order));
+		self
+	}
+
+	pub fn limit(mut self, count:usize) -> Self {
+		self.limit = Some(count);
+		self
+	}
+
+	fn has_tags(&self, food:&Food) -> bool {
+		self.tags.iter().all(|wanted| food.tags.split('|').any(|t| t.trim().to_lowercase() == *wanted))
+	}
+}
+
+// "protein density", "fat per 100 g", "sodium".  The basis is None if it isn't given.
+fn parse_measure(text:&str) -> std::result::Result<(Nutrient, Option<Basis>), String> {
+	let (name, basis) = split_basis(text.trim());
+	let (name, basis) = match name.strip_suffix("density") {
+		Some(name) if basis.is_none() => (name, Some(Basis::Per100Kilocalories)),
+		_ => (name, basis),
+	};
+	let nutrient = Nutrient::from_name(name).ok_or_else(|| format!("'{}' isn't a nutrient", name.trim()))?;
+	Ok((nutrient, basis))
+}
+
+fn split_basis(text:&str) -> (&str, Option<Basis>) {
+	for (suffix, basis) in [("per 100 g", Basis::Per100Grams), ("per 100g", Basis::Per100Grams), ("per 100 kcal", Basis::Per100Kilocalories), ("per 100kcal", Basis::Per100Kilocalories)] {
+		if let Some(rest) = text.strip_suffix(suffix) {
+			return (rest.trim_end(), Some(basis));
+		}
+	}
+	(text, None)
+}
+
+// "<measure> <comparison> <number> [unit] [per 100 g or per 100 kcal]"
+fn parse_filter(clause:&str) -> std::result::Result<NutrientFilter, String> {
+	let position = clause.find(['<', '>', '=']).ok_or("expected a comparison like < or >=")?;
+	let (left, right) = clause.split_at(position);
+	let (comparison, right) = match (right.as_bytes()[0], right.as_bytes().get(1)) {
+		(b'<', Some(b'=')) => (Comparison::LessOrEqual, &right[2..]),
+		(b'>', Some(b'=')) => (Comparison::GreaterOrEqual, &right[2..]),
+		(b'<', _) => (Comparison::Less, &right[1..]),
+		(b'>', _) => (Comparison::Greater, &right[1..]),
+		_ => (Comparison::Equal, &right[1..]),
+	};
+
+	let (nutrient, left_basis) = parse_measure(left)?;
+	let (amount, right_basis) = split_basis(right.trim());
+	let basis = match (left_basis, right_basis) {
+		(Some(a), Some(b)) if a != b => { return Err("it's measured two different ways".to_string()); },
+		(a, b) => a.or(b).unwrap_or(Basis::Per100Grams),
+	};
+	let number_end = amount.find(|c:char| !(c.is_ascii_digit() || c == '.' || c == '-')).unwrap_or(amount.len());
+	let limit: f32 = amount[..number_end].parse().map_err(|_| format!("'{}' isn't a number", amount))?;
+	let limit = match amount[number_end..].trim() {
+		"" => limit,
+		unit => convert_unit(limit, unit, nutrient.unit()).ok_or_else(|| format!("{} is measured in {}, not {}", nutrient.name(), nutrient.unit(), unit))?,
+	};
+	Ok(NutrientFilter { nutrient, basis, comparison, limit })
+}
+
+fn per_basis(amount:Option<f32>, calories:f32, basis:Basis) -> Option<f32> {
+	match basis {
+		Basis::Per100Grams => amount,
+		Basis::Per100Kilocalories if calories > 0.0 => amount.map(|a| 100.0 * a / calories),
+		Basis::Per100Kilocalories => None,
+	}
+}
+
+/// Per-100g nutrient amounts of every food, one column per nutrient, each also kept sorted so a filter can jump
+/// straight to the foods in range.  See FoodDB::build_nutrient_index.
+pub(crate) struct NutrientIndex {
+	columns: Vec<Vec<Option<f32>>>, // [nutrient][position in FoodDB.foods]
+	sorted: Vec<Vec<(f32, usize)>>, // [nutrient], (amount, position), lowest first, foods without the nutrient left out.
+	pub stale: bool, // A food may have changed since.  Scan instead until the next rebuild.
+}
+
+impl NutrientIndex {
+	pub fn new(foods:&[Food]) -> Self {
+		let columns: Vec<Vec<Option<f32>>> = (0..NUTRIENT_COUNT).map(|n| foods.iter().map(|f| Nutrient(n).amount(&f.nutrition)).collect()).collect();
+		let sorted = columns.iter().map(|column| {
+			let mut sorted: Vec<(f32, usize)> = column.iter().enumerate().filter_map(|(position, amount)| amount.map(|a| (a, position))).collect();
+			sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+			sorted
+		}).collect();
+		NutrientIndex { columns, sorted, stale: false }
+	}
+
+	fn amount(&self, nutrient:Nutrient, basis:Basis, position:usize) -> Option<f32> {
+		let calories = self.columns[Nutrient::CALORIES.0][position].unwrap_or(0.0);
+		per_basis(self.columns[nutrient.0][position], calories, basis)
+	}
+
+	// Positions of the foods that could pass a per-100g filter.
+	fn candidates(&self, filter:&NutrientFilter) -> &[(f32, usize)] {
+		let sorted = &self.sorted[filter.nutrient.0];
+		let below = |inclusive:bool| sorted.partition_point(|(a, _)| if inclusive { *a <= filter.limit } else { *a < filter.limit });
+		// Equal allows a little slack, so take the whole neighbourhood and let the filter itself decide.
+		let slack = 1e-3 * filter.limit.abs().max(1.0);
+		match filter.comparison {
+			Comparison::Less => &sorted[..below(false)],
+			Comparison::LessOrEqual => &sorted[..below(true)],
+			Comparison::Greater => &sorted[below(true)..],
+			Comparison::GreaterOrEqual => &sorted[below(false)..],
+			Comparison::Equal => {
+				let start = sorted.partition_point(|(a, _)| *a < filter.limit - slack);
+				let end = sorted.partition_point(|(a, _)| *a <= filter.limit + slack);
+				&sorted[start..end.max(start)]
+			},
+		}
+	}
+}
+
+impl FoodDB {
+	/// Foods matching the query, in the order it asks for (database order if none).
+	pub fn query_foods(&self, query:&FoodQuery) -> Vec<FoodID> {
+		let mut matches: Vec<(usize, Option<f32>)> = match self.nutrient_index.as_ref().filter(|index| !index.stale) {
+			Some(index) => {
+				// Start from the narrowest per-100g filter, if there is one.
+				let narrowest = query.filters.iter().filter(|f| f.basis == Basis::Per100Grams).map(|f| index.candidates(f)).min_by_key(|c| c.len());
+				let mut positions: Vec<usize> = match narrowest {
+					Some(candidates) => candidates.iter().map(|(_, position)| *position).collect(),
+					None => (0..self.foods.len()).collect(),
+				};
+				positions.sort_unstable();
+				self.collect_matches(query, positions, |nutrient, basis, position| index.amount(nutrient, basis, position))
+			},
+			None => self.collect_matches(query, 0..self.foods.len(), |nutrient, basis, position| {
+				let nutrition = &self.foods[position].nutrition;
+				per_basis(nutrient.amount(nutrition), nutrition.calories, basis)
+			}),
+		};
+
+		if let Some((_, _, order)) = query.sort {
+			matches.sort_by(|(_, a), (_, b)| match (a, b) {
+				(Some(a), Some(b)) if order == SortOrder::Ascending => a.total_cmp(b),
+				(Some(a), Some(b)) => b.total_cmp(a),
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(None, None) => Ordering::Equal,
+			});
+		}
+		matches.truncate(query.limit.unwrap_or(usize::MAX));
+		matches.into_iter().map(|(position, _)| self.foods[position].id).collect()
+	}
+
+	/// Parse a text query and run it.
+	pub fn query_foods_str(&self, query:&str) -> Result<Vec<FoodID>> {
+		Ok(self.query_foods(&FoodQuery::parse(query)?))
+	}
+
+	/// Keep per-100g nutrient amounts in columns, which makes query_foods much faster on big databases.  Like the search
+	/// index, it's rebuilt by reindex, imports and deletions.  Between a new_food or food_mut and the next rebuild,
+	/// query_foods scans the foods instead, so results are never out of date.  Editing FoodDB.foods directly isn't noticed.
+	pub fn build_nutrient_index(&mut self) {
+		self.nutrient_index = Some(NutrientIndex::new(&self.foods));
+	}
+
+	// Each position that passes, with the amount to sort it by.
+	fn collect_matches<P, A>(&self, query:&FoodQuery, positions:P, amount:A) -> Vec<(usize, Option<f32>)>
+	where P: IntoIterator<Item=usize>, A: Fn(Nutrient, Basis, usize) -> Option<f32> {
+		positions.into_iter()
+			.filter(|&position| query.filters.iter().all(|f| amount(f.nutrient, f.basis, position).is_some_and(|a| f.comparison.holds(a, f.limit))))
+			.filter(|&position| query.has_tags(&self.foods[position]))
+			.map(|position| (position, query.sort.and_then(|(nutrient, basis, _)| amount(nutrient, basis, position))))
+			.collect()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+
+	fn macro_db() -> FoodDB {
+		let mut db = FoodDB::new();
+		for (name, calories, proteins, fats, carbohydrates, sodium, tags) in [
+			("Chicken breast, roasted", 165.0, 31.0, 3.6, 0.0, Some(74.0), "Poultry Products"),
+			("Tofu, firm", 76.0, 8.0, 4.8, 1.9, Some(7.0), "Vegetarian|Legume Products"),
+			("Lentils, boiled", 116.0, 9.0, 0.4, 20.0, Some(2.0), "Vegetarian|Legume Products"),
+			("Cheddar cheese", 403.0, 25.0, 33.0, 1.3, Some(621.0), "Vegetarian|Dairy and Egg Products"),
+			("Egg whites", 52.0, 11.0, 0.2, 0.7, None, "vegetarian|Dairy and Egg Products"),
+			("Broccoli, raw", 34.0, 2.8, 0.4, 7.0, Some(33.0), "Vegetarian|Vegetables"),
+			("Water", 0.0, 0.0, 0.0, 0.0, Some(4.0), "Vegetarian|Beverages"),
+		] {
+			let food = db.new_food();
+			food.name = name.to_string();
+			food.tags = tags.to_string();
+			food.nutrition = Nutrients { calories, proteins, fats, carbohydrates, sodium, ..Nutrients::default() };
+		}
+		db.reindex();
+		db
+	}
+
+	fn names(db: &FoodDB, ids: Vec<FoodID>) -> Vec<String> {
+		ids.into_iter().map(|id| db.food(id).unwrap().name.clone()).collect()
+	}
+
+	#[test]
+	fn text_and_builder_queries() {
+		let mut db = macro_db();
+		let text = "protein per 100 kcal > 10, fat < 5 g per 100 g, tag:vegetarian, sorted by protein density";
+		let built = FoodQuery::new()
+			.filter(Nutrient::PROTEINS, Basis::Per100Kilocalories, Comparison::Greater, 10.0)
+			.filter(Nutrient::FATS, Basis::Per100Grams, Comparison::Less, 5.0)
+			.tag("Vegetarian")
+			.sort_by(Nutrient::PROTEINS, Basis::Per100Kilocalories, SortOrder::Descending);
+		assert_eq!(FoodQuery::parse(text).unwrap(), built);
+		assert_eq!(names(&db, db.query_foods(&built)), vec!["Egg whites", "Tofu, firm"]);
+
+		// Units are converted, and foods that don't list a nutrient don't pass filters on it.
+		assert_eq!(names(&db, db.query_foods_str("sodium <= 0.033 g, sort by sodium asc").unwrap()), vec!["Lentils, boiled", "Water", "Tofu, firm", "Broccoli, raw"]);
+		assert_eq!(names(&db, db.query_foods_str("Saturated Fat < 1").unwrap()), Vec::<String>::new());
+		// Nothing has a density without calories, so water sorts last.
+		assert_eq!(names(&db, db.query_foods_str("tag:beverages, sort by protein per 100 kcal").unwrap()), vec!["Water"]);
+		assert_eq!(names(&db, db.query_foods_str("carbs >= 7, sort by energy, limit 2").unwrap()), vec!["Lentils, boiled", "Broccoli, raw"]);
+		assert_eq!(names(&db, db.query_foods_str("calories = 76").unwrap()), vec!["Tofu, firm"]);
+		assert_eq!(db.query_foods_str("").unwrap().len(), 7);
+
+		// The column index gives the same answers.
+		let queries = [text, "sodium <= 0.033 g, sort by sodium asc", "protein > 9", "protein >= 9", "fat < 0.4", "fat <= 0.4, sort by fat",
+			"calories = 76", "protein density > 20, sort by protein asc", "tag:legume products, limit 1"];
+		let scanned: Vec<Vec<FoodID>> = queries.iter().map(|q| db.query_foods_str(q).unwrap()).collect();
+		db.build_nutrient_index();
+		for (query, expected) in queries.iter().zip(scanned) {
+			assert_eq!(db.query_foods_str(query).unwrap(), expected, "{}", query);
+		}
+		// And keeps up with new and edited foods, even before the next reindex.
+		let seitan = db.new_food().id;
+		db.food_mut(seitan).unwrap().nutrition = Nutrients { calories: 370.0, proteins: 75.0, fats: 1.9, carbohydrates: 14.0, ..Nutrients::default() };
+		assert_eq!(db.query_foods_str("protein > 30").unwrap(), vec![0, seitan]);
+		let chicken_protein = db.food(0).unwrap().nutrition.proteins;
+		db.food_mut(0).unwrap().nutrition.proteins = 29.0;
+		assert_eq!(db.query_foods_str("protein > 30").unwrap(), vec![seitan]);
+		db.reindex();
+		assert!(!db.nutrient_index.as_ref().unwrap().stale);
+		assert_eq!(db.query_foods_str("protein > 30").unwrap(), vec![seitan]);
+		db.food_mut(0).unwrap().nutrition.proteins = chicken_protein;
+		// And with recipes whose nutrition changes as ingredients go in.
+		let soup = db.new_recipe("Lentil soup", vec![(2, FoodQuantity::Mass(100.0)), (6, FoodQuantity::Mass(100.0))]).unwrap();
+		db.add_ingredient(soup, 0, FoodQuantity::Mass(100.0)).unwrap();
+		let before_reindex = db.query_foods_str("protein > 10, calories < 200").unwrap();
+		db.reindex();
+		assert_eq!(db.query_foods_str("protein > 10, calories < 200").unwrap(), before_reindex);
+		assert_eq!(names(&db, before_reindex), vec!["Chicken breast, roasted", "Egg whites", "Lentil soup"]);
+	}
+
+	#[test]
+	fn bad_queries_say_why() {
+		for (query, complaint) in [
+			("protien > 10", "'protien' isn't a nutrient"),
+			("fat < 5 kcal", "fats is measured in g, not kcal"),
+			("protein 10", "expected a comparison"),
+			("protein > lots", "'lots' isn't a number"),
+			("protein density > 10 per 100 g", "measured two different ways"),
+			("limit ten", "whole number"),
+		] {
+			match FoodQuery::parse(query) {
+				Err(Error::Validation(message)) => assert!(message.contains(complaint), "{}: {}", query, message),
+				_ => panic!("Expected '{}' to be rejected.", query),
+			}
+		}
+	}
+}
@@ -0,0 +1,299 @@
+use crate::error::{Error, Result};
+use crate::food::{Food, FoodID, FoodQuantity};
+use crate::nutrition::Nutrients;
+use crate::FoodDB;
+
+/// Common ways of cooking a recipe, for estimating how much the finished dish weighs compared to its raw ingredients.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CookingMethod {
+	BoiledRice,
+	BoiledPasta,
+	BoiledDriedLegumes,
+	CookedOats,
+	RoastedMeat,
+	GrilledMeat,
+	PanBrownedGroundMeat,
+	RoastedPoultry,
+	BakedFish,
+	BoiledVegetables,
+	SauteedVegetables,
+	SimmeredSoupOrStew,
+	BakedBread,
+}
+
+// Cooked mass / raw mass.  Rounded averages in the spirit of the USDA Table of Cooking Yields; real dishes will vary.
+const YIELD_FACTORS: [(CookingMethod, f32); 13] = [
+	(CookingMethod::BoiledRice, 3.0),
+	(CookingMethod::BoiledPasta, 2.25),
+	(CookingMethod::BoiledDriedLegumes, 2.4),
+	(CookingMethod::CookedOats, 2.6),
+	(CookingMethod::RoastedMeat, 0.7),
+	(CookingMethod::GrilledMeat, 0.72),
+	(CookingMethod::PanBrownedGroundMeat, 0.75),
+	(CookingMethod::RoastedPoultry, 0.75),
+	(CookingMethod::BakedFish, 0.8),
+	(CookingMethod::BoiledVegetables, 0.95),
+	(CookingMethod::SauteedVegetables, 0.8),
+	(CookingMethod::SimmeredSoupOrStew, 0.85),
+	(CookingMethod::BakedBread, 0.88),
+];
+
+impl CookingMethod {
+	pub fn yield_factor(self) -> f32 {
+		YIELD_FACTORS.iter().find(|(method, _)| *method == self).map(|(_, factor)| *factor).expect("Every cooking method has a yield factor.")
+	}
+
+	pub fn all() -> impl Iterator<Item=CookingMethod> {
+		YIELD_FACTORS.iter().map(|(method, _)| *method)
+	}
+}
+
+// Recipes are Foods with ingredients.  Their nutrition is the sum of their ingredients' nutrition, spread over the
+// mass of the finished dish: cooked_mass if we know it, otherwise the combined mass of everything that went in.
+impl FoodDB {
+	/// Nutrition for an amount of any food.  Composite foods are resolved through their ingredients, recursively.
+	pub fn resolve_nutrition(&self, food_id: FoodID, quantity: FoodQuantity) -> Result<Nutrients> {
+		let food = self.food(food_id)?;
+		self.resolve_food_nutrition(food, &quantity, &mut vec![])
+	}
+
+	/// Create a recipe from existing foods.  Its per-100g nutrition is cached in `nutrition` for display.
+	pub fn new_recipe(&mut self, name: &str, ingredients: Vec<(FoodID, FoodQuantity)>) -> Result<FoodID> {
+		if ingredients.is_empty() {
+			return Err(Error::Validation(format!("recipe '{}' needs at least one ingredient", name)));
+		}
+		for (ingredient, _) in &ingredients {
+			self.food(*ingredient)?;
+		}
+		let recipe = self.push_food(Food {
+			name: name.to_string(),
+			ingredients,
+			..Food::default()
+		});
+		self.recompute_recipe_nutrition(recipe)?;
+		Ok(recipe)
+	}
+
+	/// Add an ingredient to a food, refusing anything that would make the food contain itself.
+	pub fn add_ingredient(&mut self, recipe: FoodID, ingredient: FoodID, quantity: FoodQuantity) -> Result<()> {
+		self.food(ingredient)?;
+		if self.contains_ingredient(ingredient, recipe)? {
+			return Err(Error::IngredientCycle(recipe));
+		}
+		self.food_mut(recipe)?.ingredients.push((ingredient, quantity));
+		self.recompute_recipe_nutrition(recipe)
+	}
+
+	/// Record what the whole finished batch weighed.  Pass None to go back to the raw ingredient total.
+	pub fn set_cooked_mass(&mut self, recipe: FoodID, grams: Option<f32>) -> Result<()> {
+		if let Some(g) = grams {
+			if !g.is_finite() || g <= 0.0 {
+				return Err(Error::Validation(format!("cooked mass must be a positive number of grams, not {}", g)));
+			}
+		}
+		if !self.food(recipe)?.is_composite() {
+			return Err(Error::Validation(format!("food {} has no ingredients, so it has no cooked mass", recipe)));
+		}
+		self.food_mut(recipe)?.cooked_mass = grams;
+		self.recompute_recipe_nutrition(recipe)
+	}
+
+	/// Estimate the cooked mass from the raw ingredient mass when the dish wasn't weighed.
+	pub fn apply_cooking_method(&mut self, recipe: FoodID, method: CookingMethod) -> Result<()> {
+		let raw_grams = self.raw_ingredient_mass(recipe)?;
+		self.set_cooked_mass(recipe, Some(raw_grams * method.yield_factor()))
+	}
+
+	/// Combined mass of a recipe's ingredients, as weighed before cooking.
+	pub fn raw_ingredient_mass(&self, recipe: FoodID) -> Result<f32> {
+		let mut total_grams = 0.0f32;
+		for (ingredient_id, quantity) in &self.food(recipe)?.ingredients {
+			total_grams += self.food(*ingredient_id)?.try_to_grams(quantity)?;
+		}
+		Ok(total_grams)
+	}
+
+	/// Refresh the cached per-100g `nutrition` of a food after its ingredients or nutrition change, and of every
+	/// recipe that uses it, however deeply.
+	pub fn recompute_recipe_nutrition(&mut self, recipe: FoodID) -> Result<()> {
+		// Each cache is worked out from the ingredients all the way down, so the order doesn't matter.
+		let mut pending = vec![recipe];
+		let mut seen = vec![];
+		while let Some(id) = pending.pop() {
+			if seen.contains(&id) {
+				continue;
+			}
+			seen.push(id);
+			let food = self.food(id)?;
+			if food.is_composite() {
+				let per_mass = self.composite_nutrition_per_mass(food, &mut vec![])?;
+				self.food_mut(id)?.nutrition = per_mass;
+			}
+			pending.extend(self.foods.iter().filter(|f| f.ingredients.iter().any(|(i, _)| *i == id)).map(|f| f.id));
+		}
+		Ok(())
+	}
+
+	fn resolve_food_nutrition(&self, food: &Food, quantity: &FoodQuantity, visiting: &mut Vec<FoodID>) -> Result<Nutrients> {
+		if !food.is_composite() {
+			return food.try_get_nutrition(quantity);
+		}
+		let scale = food.try_scale_factor(quantity)?;
+		Ok(self.composite_nutrition_per_mass(food, visiting)? * scale)
+	}
+
+	// Nutrients in `food.mass` grams of the finished recipe.
+	fn composite_nutrition_per_mass(&self, food: &Food, visiting: &mut Vec<FoodID>) -> Result<Nutrients> {
+		if visiting.contains(&food.id) {
+			return Err(Error::IngredientCycle(food.id));
+		}
+		visiting.push(food.id);
+
+		let mut total = Nutrients::zero();
+		let mut total_grams = 0.0f32;
+		for (ingredient_id, quantity) in &food.ingredients {
+			let ingredient = self.food(*ingredient_id)?;
+			total += self.resolve_food_nutrition(ingredient, quantity, visiting)?;
+			total_grams += ingredient.try_to_grams(quantity)?;
+		}
+		visiting.pop();
+
+		let total_grams = food.cooked_mass.unwrap_or(total_grams);
+		if !total_grams.is_finite() || total_grams <= 0.0 {
+			return Err(Error::Validation(format!("recipe '{}' has no usable total mass", food.name)));
+		}
+		Ok(total * (food.mass as f32 / total_grams))
+	}
+
+	// Does `haystack` use `needle` anywhere in its ingredient tree (or is it `needle`)?
+	fn contains_ingredient(&self, haystack: FoodID, needle: FoodID) -> Result<bool> {
+		if haystack == needle {
+			return Ok(true);
+		}
+		let mut pending = vec![haystack];
+		let mut seen = vec![];
+		while let Some(id) = pending.pop() {
+			if seen.contains(&id) {
+				continue;
+			}
+			seen.push(id);
+			for (ingredient, _) in &self.food(id)?.ingredients {
+				if *ingredient == needle {
+					return Ok(true);
+				}
+				pending.push(*ingredient);
+			}
+		}
+		Ok(false)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+
+	fn simple_food(db: &mut FoodDB, name: &str, calories: f32, proteins: f32) -> FoodID {
+		let f = db.new_food();
+		f.name = name.to_string();
+		f.nutrition.calories = calories;
+		f.nutrition.proteins = proteins;
+		f.servings_in_100g = 1.0;
+		f.id
+	}
+
+	#[test]
+	fn recipe_nutrition_comes_from_ingredients() {
+		let mut db = FoodDB::new();
+		let rice = simple_food(&mut db, "Rice", 360.0, 7.0);
+		let beans = simple_food(&mut db, "Beans", 340.0, 21.0);
+
+		// 100g rice + 100g beans: 700 kcal in 200g.
+		let rice_and_beans = db.new_recipe("Rice and beans", vec![(rice, FoodQuantity::Mass(100.0)), (beans, FoodQuantity::Mass(100.0))]).unwrap();
+		assert_eq!(db.food(rice_and_beans).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 350);
+		assert_eq!(db.resolve_nutrition(rice_and_beans, FoodQuantity::Mass(200.0)).unwrap().rounded_energy(EnergyUnit::Kilocalories), 700);
+		assert!((db.resolve_nutrition(rice_and_beans, FoodQuantity::Mass(100.0)).unwrap().proteins - 14.0).abs() < 1e-4);
+
+		// Nested: a burrito is 50g tortilla (one serving, 100g) and 150g rice and beans.
+		let tortilla = simple_food(&mut db, "Tortilla", 310.0, 8.0);
+		let burrito = db.new_recipe("Burrito", vec![(tortilla, FoodQuantity::Serving(0.5)), (rice_and_beans, FoodQuantity::Mass(150.0))]).unwrap();
+		assert_eq!(db.resolve_nutrition(burrito, FoodQuantity::Mass(200.0)).unwrap().rounded_energy(EnergyUnit::Kilocalories), 155 + 525);
+
+		// Logging a recipe uses the live ingredient data.
+		let lunch = db.new_meal();
+		db.add_food_to_meal(lunch, burrito, FoodQuantity::Mass(200.0)).unwrap();
+		assert_eq!(db.meal(lunch).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 680);
+		db.food_mut(beans).unwrap().nutrition.calories = 260.0;
+		db.recompute_meal_nutrients(lunch).unwrap();
+		assert_eq!(db.meal(lunch).unwrap().nutrients.rounded_energy(EnergyUnit::Kilocalories), 155 + 465);
+	}
+
+	#[test]
+	fn recipes_using_a_changed_food_are_refreshed() {
+		let mut db = FoodDB::new();
+		let flour = simple_food(&mut db, "Flour", 360.0, 10.0);
+		let dough = db.new_recipe("Dough", vec![(flour, FoodQuantity::Mass(100.0))]).unwrap();
+		let pizza = db.new_recipe("Pizza", vec![(dough, FoodQuantity::Mass(100.0))]).unwrap();
+		let calzone = db.new_recipe("Calzone", vec![(pizza, FoodQuantity::Mass(50.0)), (dough, FoodQuantity::Mass(50.0))]).unwrap();
+		assert_eq!(db.food(calzone).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 360);
+
+		// Water halves the dough's density, and everything made from it follows.
+		let water = simple_food(&mut db, "Water", 0.0, 0.0);
+		db.add_ingredient(dough, water, FoodQuantity::Mass(100.0)).unwrap();
+		for recipe in [dough, pizza, calzone] {
+			assert_eq!(db.food(recipe).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 180);
+		}
+
+		// So do edits to a plain ingredient, once they are announced.
+		db.food_mut(flour).unwrap().nutrition.calories = 300.0;
+		db.recompute_recipe_nutrition(flour).unwrap();
+		assert_eq!(db.food(calzone).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 150);
+
+		// New recipes are searchable straight away.
+		assert_eq!(db.search_foods("calzone", None)[0].id, calzone);
+		assert_eq!(db.search_foods("pizza", None)[0].id, pizza);
+	}
+
+	#[test]
+	fn cooked_mass_changes_density() {
+		let mut db = FoodDB::new();
+		let dry_rice = simple_food(&mut db, "Rice, dry", 360.0, 7.0);
+		let rice = db.new_recipe("Steamed rice", vec![(dry_rice, FoodQuantity::Mass(100.0))]).unwrap();
+		assert_eq!(db.resolve_nutrition(rice, FoodQuantity::Mass(100.0)).unwrap().rounded_energy(EnergyUnit::Kilocalories), 360);
+
+		// It triples in weight, so 100g of cooked rice is a third as energy-dense.
+		db.apply_cooking_method(rice, CookingMethod::BoiledRice).unwrap();
+		assert_eq!(db.food(rice).unwrap().cooked_mass, Some(300.0));
+		assert_eq!(db.resolve_nutrition(rice, FoodQuantity::Mass(300.0)).unwrap().rounded_energy(EnergyUnit::Kilocalories), 360);
+		assert_eq!(db.food(rice).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 120);
+
+		// Weighed the pot instead.
+		db.set_cooked_mass(rice, Some(240.0)).unwrap();
+		assert_eq!(db.resolve_nutrition(rice, FoodQuantity::Mass(120.0)).unwrap().rounded_energy(EnergyUnit::Kilocalories), 180);
+		db.set_cooked_mass(rice, None).unwrap();
+		assert_eq!(db.food(rice).unwrap().nutrition.rounded_energy(EnergyUnit::Kilocalories), 360);
+
+		assert!(matches!(db.set_cooked_mass(rice, Some(0.0)), Err(Error::Validation(_))));
+		assert!(matches!(db.set_cooked_mass(dry_rice, Some(10.0)), Err(Error::Validation(_))));
+		assert!(CookingMethod::all().all(|m| m.yield_factor() > 0.0));
+		assert_eq!(CookingMethod::all().count(), 13);
+	}
+
+	#[test]
+	fn recipe_cycles_are_detected() {
+		let mut db = FoodDB::new();
+		let salt = simple_food(&mut db, "Salt", 0.0, 0.0);
+		let sauce = db.new_recipe("Sauce", vec![(salt, FoodQuantity::Mass(1.0))]).unwrap();
+		let stew = db.new_recipe("Stew", vec![(sauce, FoodQuantity::Mass(50.0))]).unwrap();
+
+		assert!(matches!(db.add_ingredient(sauce, stew, FoodQuantity::Mass(5.0)), Err(Error::IngredientCycle(_))));
+		assert!(matches!(db.add_ingredient(sauce, sauce, FoodQuantity::Mass(5.0)), Err(Error::IngredientCycle(_))));
+
+		// Sneak one in behind the API's back.
+		db.food_mut(sauce).unwrap().ingredients.push((stew, FoodQuantity::Mass(5.0)));
+		assert!(matches!(db.resolve_nutrition(stew, FoodQuantity::Mass(10.0)), Err(Error::IngredientCycle(_))));
+
+		assert!(matches!(db.new_recipe("Nothing", vec![]), Err(Error::Validation(_))));
+		assert!(matches!(db.new_recipe("Ghost", vec![(999, FoodQuantity::Mass(1.0))]), Err(Error::UnknownFood(999))));
+	}
+}
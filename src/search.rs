@@ -1,17 +1,92 @@
 use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use crate::barcode::normalize_barcode;
 use crate::food::{FoodID, Food};
+use crate::meal::Meal;
+use crate::usage::{SearchContext, UsageStats};
 use hashbrown::HashMap;
 
 pub struct FoodSearchResult {
 	pub id: FoodID,
 	pub name: String,
-	relevance: f32
+	pub relevance: f32, // From 0 to 1.  Around 0.8 and up is almost certainly what was meant.
+}
+
+// BM25 term weighting.  K1 is how quickly repeats of a word stop counting; B is how much longer fields are discounted.
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+// A word in the name says more about a food than one in its manufacturer or tags.
+const FIELD_WEIGHTS: [(SearchField, f32); 3] = [(SearchField::Name, 1.0), (SearchField::Manufacturer, 0.6), (SearchField::Tags, 0.4)];
+// A half-typed word might be headed somewhere else.
+const UNFINISHED_WORD_WEIGHT: f32 = 0.8;
+// What a name starting with the query, give or take typos, is worth when no whole word matched.  Halved per typo.
+const TYPO_MATCH_SCORE: f32 = 1.5;
+const EXACT_MATCH_BOOST: f32 = 4.0;
+const PREFIX_BOOST: f32 = 1.0;
+// Per character of the name, so "Milk, whole" beats "Milk, whole, 3.25% milkfat, with added vitamin D".
+const NAME_LENGTH_PENALTY: f32 = 0.01;
+// The raw score that maps to a relevance of one half.
+const HALF_RELEVANCE_SCORE: f32 = 1.0;
+
+// How often one word appears in one field of one food.
+struct Posting {
+	id: FoodID,
+	field: SearchField,
+	count: u32,
+}
+
+// Everywhere one word appears.  Postings for a food are next to each other, in field order.
+#[derive(Default)]
+struct TokenPostings {
+	foods: u32, // How many foods have the word, for its IDF.
+	postings: Vec<Posting>,
 }
 
 // Do not derive serialize/deserialize.  Regenerate index on init.
 pub struct SearchIndex {
 	fulltext_index: BTreeMap<String, FoodID>,
 	autocomplete_index: PrefixTree,
+	barcode_index: HashMap<String, Vec<FoodID>>, // Keyed by normalized barcode.
+	token_index: BTreeMap<String, TokenPostings>, // Every word of every searchable field, stemmed.
+	field_lengths: HashMap<(FoodID, SearchField), u32>, // In words.
+	total_field_lengths: HashMap<SearchField, u32>, // Every food has every field, so the average is this over the food count.
+	names: HashMap<FoodID, String>,
+	usage: UsageStats, // Rebuilt from meals, not foods, so reindex leaves it alone.
+}
+
+/// The parts of a food that text search looks at.  A query term can be limited to one with a prefix like `brand:`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SearchField {
+	Name,
+	Manufacturer,
+	Tags,
+}
+
+impl SearchField {
+	fn from_prefix(prefix:&str) -> Option<SearchField> {
+		match prefix.to_lowercase().as_str() {
+			"name" => Some(SearchField::Name),
+			"brand" | "manufacturer" => Some(SearchField::Manufacturer),
+			"tag" | "tags" => Some(SearchField::Tags),
+			_ => None,
+		}
+	}
+}
+
+// One word of a query.  Only the word being typed, at the very end, may be unfinished.
+struct QueryTerm {
+	field: Option<SearchField>,
+	word: String, // Case-folded, not stemmed.
+	unfinished: bool,
+}
+
+impl QueryTerm {
+	fn looks_in(&self, field:SearchField) -> bool {
+		match self.field {
+			Some(f) => f == field,
+			None => true,
+		}
+	}
 }
 
 impl Default for SearchIndex {
@@ -19,6 +94,12 @@ impl Default for SearchIndex {
 		SearchIndex {
 			fulltext_index: BTreeMap::new(),
 			autocomplete_index: PrefixTree::new(),
+			barcode_index: HashMap::new(),
+			token_index: BTreeMap::new(),
+			field_lengths: HashMap::new(),
+			total_field_lengths: HashMap::new(),
+			names: HashMap::new(),
+			usage: UsageStats::default(),
 		}
 	}
 }
@@ -28,107 +109,295 @@ impl SearchIndex {
 		SearchIndex::default()
 	}
 
-	pub fn reindex(&mut self, food_db:&Vec<Food>) {
+	pub fn reindex(&mut self, food_db:&[Food]) {
 		self.fulltext_index = BTreeMap::new();
 		self.autocomplete_index = PrefixTree::new();
-		food_db.iter().for_each(|f|{
-			self.fulltext_index.insert(f.name.clone(), f.id);
-			self.autocomplete_index.add_word(f.name.clone());
-		});
+		self.barcode_index = HashMap::new();
+		self.token_index = BTreeMap::new();
+		self.field_lengths = HashMap::new();
+		self.total_field_lengths = HashMap::new();
+		self.names = HashMap::new();
+		food_db.iter().for_each(|f| self.add_food(f));
+	}
+
+	/// Index one food that isn't in the index yet, without a full reindex.
+	pub fn add_food(&mut self, f:&Food) {
+		self.fulltext_index.insert(f.name.clone(), f.id);
+		self.autocomplete_index.add_word(f.name.clone());
+		self.names.insert(f.id, f.name.clone());
+		for (field, text) in [(SearchField::Name, &f.name), (SearchField::Manufacturer, &f.manufacturer), (SearchField::Tags, &f.tags)] {
+			let tokens = tokenize(text);
+			self.field_lengths.insert((f.id, field), tokens.len() as u32);
+			*self.total_field_lengths.entry(field).or_insert(0) += tokens.len() as u32;
+			for token in tokens {
+				let token = self.token_index.entry(token).or_default();
+				// Foods are indexed one at a time, so a repeat of this word in this field can only be the last posting.
+				match token.postings.last_mut() {
+					Some(p) if p.id == f.id && p.field == field => { p.count += 1; },
+					Some(p) if p.id == f.id => { token.postings.push(Posting { id: f.id, field, count: 1 }); },
+					_ => {
+						token.foods += 1;
+						token.postings.push(Posting { id: f.id, field, count: 1 });
+					},
+				}
+			}
+		}
+		// Older files may hold codes as they were typed.  Ones that don't validate can't be looked up.
+		for code in f.barcodes.iter().filter_map(|b| normalize_barcode(b).ok()) {
+			self.add_barcode(code, f.id);
+		}
 	}
 
-	pub fn new(food_db:&Vec<Food>) -> Self {
+	pub fn new(food_db:&[Food]) -> Self {
 		let mut new_index = SearchIndex::empty();
 		new_index.reindex(food_db);
 		new_index
 	}
 
-	pub fn search(&self, food_name:&String, max_results:Option<u8>) -> Vec<FoodSearchResult> {
-		let mut matches = vec![];
-		let max_results = max_results.unwrap_or(10);
+	pub fn reindex_usage(&mut self, meals:&[Meal]) {
+		self.usage.rebuild(meals);
+	}
 
-		// If we have an exact match, return it.
-		if let Some(id) = self.fulltext_index.get(food_name) {
-			matches.push(FoodSearchResult {
-				id: *id,
-				name: food_name.clone(),
-				relevance: 1.0f32,
-			});
+	/// Count a food being logged in a meal at `time`.
+	pub fn record_use(&mut self, food_id:FoodID, time:DateTime<Utc>, meal_name:&str) {
+		self.usage.record(food_id, time, meal_name);
+	}
+
+	/// Take back a use counted by record_use, given the same time and meal name.
+	pub fn forget_use(&mut self, food_id:FoodID, time:DateTime<Utc>, meal_name:&str) {
+		self.usage.forget(food_id, time, meal_name);
+	}
+
+	/// Make a food findable by a barcode without a full reindex.  `code` must already be normalized.
+	pub fn add_barcode(&mut self, code:String, food_id:FoodID) {
+		let ids = self.barcode_index.entry(code).or_default();
+		if !ids.contains(&food_id) {
+			ids.push(food_id);
 		}
-		
-		// Append other matches.
-		for name in self.autocomplete_index.fuzzy_matches(food_name, max_results) {
+	}
+
+	/// Foods carrying a barcode.  `code` must already be normalized.
+	pub fn find_barcode(&self, code:&str) -> &[FoodID] {
+		self.barcode_index.get(code).map(|ids| ids.as_slice()).unwrap_or(&[])
+	}
+
+	/// Foods matching the query, best first.  A food matches if every word of the query is somewhere in its name,
+	/// manufacturer or tags, or if its name starts with the query give or take a few typos.  Rare words count for more
+	/// than common ones, names equal to or starting with the query are boosted, and long names are slightly penalized.
+	pub fn search(&self, food_name:&str, max_results:Option<u8>) -> Vec<FoodSearchResult> {
+		self.search_in_context(food_name, max_results, &SearchContext::default())
+	}
+
+	/// Like search, but foods the user logs often and lately come first, more so if they are usually logged at this
+	/// time of day or in this kind of meal.
+	pub fn search_in_context(&self, food_name:&str, max_results:Option<u8>, context:&SearchContext) -> Vec<FoodSearchResult> {
+		let max_results = max_results.unwrap_or(10);
+		let mut scores = self.score_words(&parse_query(food_name));
+		for (name, distance) in self.autocomplete_index.fuzzy_matches(food_name, max_results) {
 			if let Some(id) = self.fulltext_index.get(&name) {
-				matches.push(FoodSearchResult {
-					id: *id,
-					name: name,
-					relevance: 0.0f32,
-				})
+				let typo_score = TYPO_MATCH_SCORE / 2f32.powi(distance as i32);
+				let score = scores.entry(*id).or_insert(0.0);
+				*score = score.max(typo_score);
 			}
 		}
-
+		let query = food_name.trim().to_lowercase();
+		let mut matches: Vec<FoodSearchResult> = scores.into_iter().filter_map(|(id, mut score)| {
+			let name = self.names.get(&id)?;
+			let lowercase_name = name.to_lowercase();
+			if lowercase_name == query {
+				score += EXACT_MATCH_BOOST;
+			} else if !query.is_empty() && lowercase_name.starts_with(&query) {
+				score += PREFIX_BOOST;
+			}
+			score /= 1.0 + NAME_LENGTH_PENALTY * name.chars().count() as f32;
+			score *= self.usage.boost(id, context);
+			Some(FoodSearchResult { id, name: name.clone(), relevance: score / (score + HALF_RELEVANCE_SCORE) })
+		}).collect();
+		matches.sort_by(|a, b| b.relevance.total_cmp(&a.relevance).then_with(|| a.name.cmp(&b.name)).then(a.id.cmp(&b.id)));
+		matches.truncate(max_results as usize);
 		matches
 	}
+
+	// Which foods have every word, ignoring typos and order.
+	#[cfg(test)]
+	fn find_words(&self, query:&str) -> hashbrown::HashSet<FoodID> {
+		self.score_words(&parse_query(query)).into_iter().map(|(id, _)| id).collect()
+	}
+
+	// BM25 for the foods that have every term, counting each term once per food, in whichever field it scores best.
+	fn score_words(&self, terms:&[QueryTerm]) -> HashMap<FoodID, f32> {
+		let food_count = self.names.len() as f32;
+		let mut found: Option<HashMap<FoodID, f32>> = None;
+		for term in terms {
+			let stem = stem(&term.word);
+			let mut matched_tokens: Vec<(&TokenPostings, f32)> = self.token_index.get(&stem).map(|p| (p, 1.0)).into_iter().collect();
+			if term.unfinished {
+				let completions = self.token_index.range(term.word.clone()..).take_while(|(token, _)| token.starts_with(&term.word));
+				matched_tokens.extend(completions.filter(|(token, _)| **token != stem).map(|(_, p)| (p, UNFINISHED_WORD_WEIGHT)));
+			}
+
+			let mut term_scores: HashMap<FoodID, f32> = HashMap::new();
+			for (token, weight) in matched_tokens {
+				let foods_with_token = token.foods as f32;
+				let idf = (1.0 + (food_count - foods_with_token + 0.5) / (foods_with_token + 0.5)).ln();
+				for p in token.postings.iter().filter(|p| term.looks_in(p.field)) {
+					let length = self.field_lengths.get(&(p.id, p.field)).copied().unwrap_or(0) as f32;
+					let average_length = (self.total_field_lengths.get(&p.field).copied().unwrap_or(0) as f32 / food_count).max(1.0);
+					let count = p.count as f32;
+					let bm25 = idf * count * (BM25_K1 + 1.0) / (count + BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length));
+					let score = term_scores.entry(p.id).or_insert(0.0);
+					*score = score.max(weight * field_weight(p.field) * bm25);
+				}
+			}
+			found = Some(match found {
+				None => term_scores,
+				Some(previous) => previous.into_iter().filter_map(|(id, score)| term_scores.get(&id).map(|s| (id, score + s))).collect(),
+			});
+		}
+		found.unwrap_or_default()
+	}
+}
+
+fn field_weight(field:SearchField) -> f32 {
+	FIELD_WEIGHTS.iter().find(|(f, _)| *f == field).map(|(_, weight)| *weight).expect("Every field has a weight.")
+}
+
+// Split on anything that isn't a letter or digit, so "Chicken breast, roasted" and "Kellogg's" give useful words.
+fn words(text:&str) -> impl Iterator<Item=String> + '_ {
+	text.split(|c:char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(|w| w.to_lowercase())
+}
+
+fn tokenize(text:&str) -> Vec<String> {
+	words(text).map(|w| stem(&w)).collect()
+}
+
+// Just enough English stemming that plurals find singulars: berries, tomatoes, peaches and oats.
+fn stem(word:&str) -> String {
+	let long_enough = word.chars().count() > 3;
+	if long_enough && word.ends_with("ies") {
+		format!("{}y", &word[..word.len() - 3])
+	} else if long_enough && ["oes", "ches", "shes", "sses", "xes"].iter().any(|suffix| word.ends_with(suffix)) {
+		word[..word.len() - 2].to_string()
+	} else if long_enough && word.ends_with('s') && !["ss", "us", "is"].iter().any(|suffix| word.ends_with(suffix)) {
+		word[..word.len() - 1].to_string()
+	} else {
+		word.to_string()
+	}
+}
+
+// "brand:kellogg corn fl" is three terms, the first only looking at manufacturers and the last still being typed.
+// A prefix that isn't a field name, like "allergen:milk", is just more words.
+fn parse_query(query:&str) -> Vec<QueryTerm> {
+	let mut terms = vec![];
+	for chunk in query.split_whitespace() {
+		let (field, text) = match chunk.split_once(':') {
+			Some((prefix, rest)) => match SearchField::from_prefix(prefix) {
+				Some(field) => (Some(field), rest),
+				None => (None, chunk),
+			},
+			None => (None, chunk),
+		};
+		terms.extend(words(text).map(|word| QueryTerm { field, word, unfinished: false }));
+	}
+	if let Some(last) = terms.last_mut() {
+		last.unfinished = !query.ends_with(char::is_whitespace);
+	}
+	terms
+}
+
+// Typos allowed for a query of each length.  Short queries are mostly prefixes of what the user means, so must be exact.
+fn typo_allowance(query_length:usize) -> usize {
+	match query_length {
+		0..=3 => 0,
+		4..=7 => 1,
+		_ => 2,
+	}
 }
 
 pub struct PrefixTree {
-	depth: usize,
 	child_trees: HashMap<char, Box<PrefixTree>>,
 	words: Vec<String>, // What full, finished words exist here?
 }
 
+// A word that starts with something close to the query.
+struct TrieMatch<'a> {
+	word: &'a str,
+	distance: usize, // Fewest edits turning the query into a prefix of the word.
+	exact_prefix: usize, // Characters the word and query share before the first typo.
+}
+
 impl PrefixTree {
 	pub fn new() -> Self {
 		PrefixTree {
-			depth: 0,
 			child_trees: HashMap::new(),
 			words: vec![],
 		}
 	}
 
 	pub fn add_word(&mut self, word:String) {
-		// Is there another letter we need to store?
-		if self.depth >= word.len() {
-			// No.  Can just add the word at this level.
-			self.words.push(word);
-		} else {
-			// We check above for length, so we can unwrap.
-			let index_character:char = word.to_lowercase().chars().nth(self.depth).unwrap();
-			// If there isn't already a subtree for this character, create one.
-			if !self.child_trees.contains_key(&index_character) {
-				let mut new_tree = PrefixTree::new();
-				new_tree.depth = self.depth + 1;
-				self.child_trees.insert(index_character, Box::new(new_tree));
-			}
-			// Use a mutable reference to recursively insert.
-			if let Some(chld) = self.child_trees.get_mut(&index_character) {
-				chld.add_word(word);
-			}
+		let mut tree = self;
+		for c in word.to_lowercase().chars() {
+			tree = tree.child_trees.entry(c).or_insert_with(|| Box::new(PrefixTree::new()));
 		}
+		tree.words.push(word);
 	}
 
-	pub fn fuzzy_matches(&self, starting_string:&String, max_results: u8) -> Vec<String> {
-		let mut matches = Vec::<String>::with_capacity(max_results as usize);
+	/// Words starting with the query, or with something a few typos away from it, best first, with the number of
+	/// typos.  Case is ignored.
+	pub fn fuzzy_matches(&self, starting_string:&str, max_results: u8) -> Vec<(String, usize)> {
+		self.ranked_matches(starting_string, max_results).into_iter().map(|m| (m.word.to_string(), m.distance)).collect()
+	}
 
-		// If we are past our depth, i.e., there are no more characters in the autocomplete sequence, give back everything at this level.
-		if self.depth >= starting_string.len() {
-			for w in &self.words {
-				matches.push(w.clone());
-			}
-			for c in self.child_trees.values() {
-				matches.extend(c.fuzzy_matches(starting_string, max_results));
-				if matches.len() > max_results as usize {
-					break;
+	// Fewer typos first, then the longest run of exactly typed characters, then the shortest word.
+	fn ranked_matches(&self, query:&str, max_results: u8) -> Vec<TrieMatch<'_>> {
+		let query: Vec<char> = query.to_lowercase().chars().collect();
+		let mut matches = vec![];
+		let first_row: Vec<usize> = (0..=query.len()).collect();
+		self.collect_matches(&query, typo_allowance(query.len()), &mut vec![first_row], &mut vec![], usize::MAX, &mut matches);
+		matches.sort_by(|a, b| {
+			a.distance.cmp(&b.distance)
+				.then(b.exact_prefix.cmp(&a.exact_prefix))
+				.then(a.word.chars().count().cmp(&b.word.chars().count()))
+				.then(a.word.cmp(b.word))
+		});
+		matches.dedup_by(|a, b| a.word == b.word);
+		matches.truncate(max_results as usize);
+		matches
+	}
+
+	// Walk the tree, keeping one row of the Damerau-Levenshtein (optimal string alignment) table per level.  rows[d][j]
+	// is the distance between the first j characters of the query and the d characters on the path to this node.
+	fn collect_matches<'a>(&'a self, query:&[char], max_distance:usize, rows:&mut Vec<Vec<usize>>, path:&mut Vec<char>, best:usize, matches:&mut Vec<TrieMatch<'a>>) {
+		let row = rows.last().expect("There is always a row for the root.");
+		// The whole query against this prefix.  Once it's close enough, everything below matches too.
+		let best = best.min(row[query.len()]);
+		if best <= max_distance {
+			let exact_prefix = path.iter().zip(query.iter()).take_while(|(a, b)| a == b).count();
+			matches.extend(self.words.iter().map(|w| TrieMatch { word: w, distance: best, exact_prefix }));
+		}
+
+		for (&c, child) in &self.child_trees {
+			let row = &rows[rows.len() - 1];
+			let mut next = vec![row[0] + 1; query.len() + 1];
+			for j in 1..=query.len() {
+				let substitution = row[j - 1] + if query[j - 1] == c { 0 } else { 1 };
+				next[j] = (row[j] + 1).min(next[j - 1] + 1).min(substitution);
+				// Two neighbouring characters swapped.
+				if j > 1 && rows.len() > 1 && query[j - 2] == c && path.last() == Some(&query[j - 1]) {
+					next[j] = next[j].min(rows[rows.len() - 2][j - 2] + 1);
 				}
 			}
-		} else { // Otherwise, grab the token at the given depth and recurse into this tree.
-			let index_char = starting_string.to_lowercase().chars().nth(self.depth).unwrap();
-			if let Some(c) = self.child_trees.get(&index_char) {
-				matches = c.fuzzy_matches(starting_string, max_results);
+			// Nothing below can get closer than the best of this row, or of the one above plus a swap.
+			let lower_bound = next.iter().min().copied().unwrap_or(0).min(row.iter().min().copied().unwrap_or(0) + 1);
+			if best > max_distance && lower_bound > max_distance {
+				continue;
 			}
+			rows.push(next);
+			path.push(c);
+			child.collect_matches(query, max_distance, rows, path, best, matches);
+			path.pop();
+			rows.pop();
 		}
-
-		matches
 	}
 }
 
@@ -139,22 +408,26 @@ mod tests {
 	use crate::search::{SearchIndex, PrefixTree};
 
 	fn bootstrap_foods() -> Vec<Food> {
-		let mut food_db = vec![];
-
-		let mut sugar = Food::default();
-		sugar.name = "Sugar".to_string();
-		sugar.manufacturer = "Old Mill".to_string();
-		food_db.push(sugar);
-
-		let mut splenda = Food::default();
-		splenda.name = "splenda".to_string();
-		food_db.push(splenda);
-
-		let mut generic_food = Food::default();
-		generic_food.name = "food".to_string();
-		food_db.push(generic_food);
-
-		food_db
+		vec![
+			Food {
+				id: 1,
+				name: "Sugar".to_string(),
+				manufacturer: "Old Mill".to_string(),
+				tags: "baking|sweetener".to_string(),
+				..Food::default()
+			},
+			Food {
+				id: 2,
+				name: "splenda".to_string(),
+				tags: "sweetener".to_string(),
+				..Food::default()
+			},
+			Food {
+				id: 3,
+				name: "food".to_string(),
+				..Food::default()
+			},
+		]
 	}
 
 	#[test]
@@ -166,16 +439,53 @@ mod tests {
 		}
 
 		// Should find both spelenda and sugar, but not 'food'.
-		let res:Vec<String> = autocomplete.fuzzy_matches(&"s".to_string(), 10);
+		let res:Vec<String> = autocomplete.fuzzy_matches("s", 10).into_iter().map(|(w, _)| w).collect();
 		assert!(res.contains(&"Sugar".to_string()));
 		assert!(res.contains(&"splenda".to_string()));
 		assert!(!res.contains(&"food".to_string()));
 
-		let empty:Vec<String> = autocomplete.fuzzy_matches(&"asdf".to_string(), 10);
+		let empty = autocomplete.fuzzy_matches("asdf", 10);
 		assert!(empty.is_empty());
 
-		let just_food:Vec<String> = autocomplete.fuzzy_matches(&"food".to_string(), 10);
-		assert_eq!(vec!["food"], just_food);
+		let just_food = autocomplete.fuzzy_matches("food", 10);
+		assert_eq!(vec![("food".to_string(), 0)], just_food);
+
+		// One typo is allowed in a word this long, and swapped letters count as one.
+		assert_eq!(autocomplete.fuzzy_matches("suagr", 10), vec![("Sugar".to_string(), 1)]);
+		assert_eq!(autocomplete.fuzzy_matches("splneda", 10), vec![("splenda".to_string(), 1)]);
+		assert!(autocomplete.fuzzy_matches("spelnad", 10).is_empty());
+		// Short queries are taken literally.
+		assert!(autocomplete.fuzzy_matches("fod", 10).is_empty());
+	}
+
+	#[test]
+	fn ranks_common_misspellings() {
+		const MISSPELLINGS: &str = include_str!("../tests/fixtures/misspellings.tsv");
+		let names = [
+			"Chicken breast, roasted", "Chickpeas, canned", "Chicory greens", "Chili con carne",
+			"Yogurt, Greek, plain", "Yolks, egg", "Broccoli, raw", "Zucchini, raw", "Spaghetti, cooked", "Spaghetti squash, baked",
+			"Avocado, raw", "Banana, raw", "Bananas, dried", "Tomato, red, raw", "Tomatoes, canned", "Quinoa, cooked",
+			"Mozzarella cheese", "Parmesan cheese", "Espresso, brewed", "Sandwich bread, white", "Cinnamon, ground",
+			"Hummus", "Hamburger", "Cereal, corn flakes", "Oatmeal, cooked", "Lettuce, romaine", "Raspberries, raw",
+			"Strawberries, raw", "Cantaloupe",
+		];
+		let foods: Vec<Food> = names.iter().enumerate().map(|(id, name)| Food { id: id as FoodID, name: name.to_string(), ..Food::default() }).collect();
+		let index = SearchIndex::new(&foods);
+
+		for line in MISSPELLINGS.lines().filter(|l| !l.starts_with('#')) {
+			let (query, expected) = line.split_once('\t').unwrap();
+			let results = index.search(query, None);
+			assert_eq!(results.first().map(|r| r.name.as_str()), Some(expected), "searching for {}", query);
+		}
+
+		// Exact prefixes beat typos, and each typo costs relevance.
+		let results = index.search("chick", None);
+		assert_eq!(results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), vec!["Chickpeas, canned", "Chicken breast, roasted", "Chicory greens"]);
+		let results = index.search("tomatoe", None);
+		assert_eq!(results[0].name, "Tomatoes, canned");
+		assert_eq!(results[1].name, "Tomato, red, raw");
+		assert!(results[0].relevance > results[1].relevance);
+		assert!(index.search("xylophone", None).is_empty());
 	}
 
 	#[test]
@@ -183,6 +493,112 @@ mod tests {
 		let foods = bootstrap_foods();
 		let index = SearchIndex::new(&foods);
 
-		let search_results = index.search(&"sugar".to_string(), None);
+		let search_results = index.search("sugar", None);
+		assert_eq!(search_results.len(), 1);
+		assert_eq!(search_results[0].name, "Sugar");
+	}
+
+	#[test]
+	fn search_every_word() {
+		let mut foods = bootstrap_foods();
+		for (id, name, manufacturer, tags) in [
+			(10, "Chicken breast, roasted", "", "Poultry Products"),
+			(11, "Chicken thigh, raw", "", "Poultry Products"),
+			(12, "Corn Flakes", "Kellogg's", "Breakfast cereals|allergen:gluten"),
+			(13, "Raisin Bran", "Kellogg's", "Breakfast cereals"),
+			(14, "Raspberries, raw", "", "Fruits and Fruit Juices"),
+			(15, "Peaches, canned", "Del Monte", "Fruits and Fruit Juices"),
+		] {
+			foods.push(Food { id, name: name.to_string(), manufacturer: manufacturer.to_string(), tags: tags.to_string(), ..Food::default() });
+		}
+		let index = SearchIndex::new(&foods);
+		let found = |query: &str| { let mut ids: Vec<FoodID> = index.find_words(query).into_iter().collect(); ids.sort(); ids };
+
+		// The key word of a USDA name is rarely first.
+		assert_eq!(found("breast"), vec![10]);
+		assert_eq!(found("roasted chicken "), vec![10]);
+		assert_eq!(found("CHICKEN"), vec![10, 11]);
+		// Plurals find singulars and the other way around.
+		assert_eq!(found("raspberry "), vec![14]);
+		assert_eq!(found("peach "), vec![15]);
+		assert_eq!(found("cereal "), vec![12, 13]);
+
+		// Manufacturer and tags are searched too, and can be asked for by name.
+		assert_eq!(found("kellogg"), vec![12, 13]);
+		assert_eq!(found("brand:kellogg bran"), vec![13]);
+		assert_eq!(found("brand:kellogg's flakes"), vec![12]);
+		assert_eq!(found("tag:sweetener"), vec![1, 2]);
+		assert_eq!(found("allergen:gluten"), vec![12]);
+		assert!(found("name:kellogg").is_empty());
+		assert!(found("brand:poultry").is_empty());
+
+		// Only the last word may be unfinished.
+		assert_eq!(found("chicken th"), vec![11]);
+		assert!(found("chicken th ").is_empty());
+		assert!(found("chick breast").is_empty());
+		assert!(found("").is_empty());
+
+		let results = index.search("breast", None);
+		assert_eq!(results.len(), 1);
+		assert_eq!(results[0].name, "Chicken breast, roasted");
+		let results: Vec<String> = index.search("kellogg", None).into_iter().map(|r| r.name).collect();
+		assert_eq!(results, vec!["Corn Flakes", "Raisin Bran"]);
+	}
+
+	#[test]
+	fn relevance_ranking() {
+		let foods: Vec<Food> = [
+			("Milk, whole, 3.25% milkfat, with added vitamin D", "", "Dairy and Egg Products"),
+			("Milk, whole", "", "Dairy and Egg Products"),
+			("Milk", "", "Dairy and Egg Products"),
+			("Chocolate milk", "", "Beverages"),
+			("Cheese, cheddar", "", "Dairy and Egg Products"),
+			("Cheese, swiss", "", "Dairy and Egg Products"),
+			("Pudding, chocolate", "", "Sweets|contains:milk"),
+			("Yogurt, plain", "Milky Way Dairy", "Dairy and Egg Products"),
+			("Swiss roll", "", "Dairy|Cakes"),
+			("Dairy roll", "", "Swiss|Cakes"),
+		].iter().enumerate().map(|(id, (name, manufacturer, tags))| Food {
+			id: id as FoodID,
+			name: name.to_string(),
+			manufacturer: manufacturer.to_string(),
+			tags: tags.to_string(),
+			..Food::default()
+		}).collect();
+		let index = SearchIndex::new(&foods);
+		let names = |query: &str| index.search(query, None).into_iter().map(|r| r.name).collect::<Vec<String>>();
+
+		// Each food once, best first: the exact name, then names starting with it, shortest first, then the rest.
+		let results = index.search("milk", None);
+		assert_eq!(results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), vec![
+			"Milk", "Milk, whole", "Milk, whole, 3.25% milkfat, with added vitamin D", "Chocolate milk", "Yogurt, plain", "Pudding, chocolate",
+		]);
+		assert!(results.windows(2).all(|pair| pair[0].relevance > pair[1].relevance));
+		assert!(results[0].relevance > 0.8 && results[0].relevance < 1.0);
+		assert!(results.iter().all(|r| r.relevance > 0.0));
+		assert_eq!(names("MILK")[0], "Milk");
+
+		// Every word has to be there.
+		assert_eq!(names("cheese swiss"), vec!["Cheese, swiss"]);
+		assert_eq!(names("chocolate")[..2], ["Chocolate milk".to_string(), "Pudding, chocolate".to_string()]);
+
+		// Rare words outweigh common ones.  Both rolls have both words, one in the name and the other in the tags, but
+		// most foods say "dairy" and few say "swiss", so the roll named for the rare word wins either way round.
+		for query in ["dairy swiss", "swiss dairy"] {
+			let results = names(query);
+			let position = |name: &str| results.iter().position(|r| r == name).unwrap();
+			assert!(position("Swiss roll") < position("Dairy roll"), "{}", query);
+		}
+
+		// A typo still finds the food, with less confidence.
+		let exact = index.search("cheese, cheddar", None);
+		let typo = index.search("cheese, chedar", None);
+		assert_eq!(exact[0].name, "Cheese, cheddar");
+		assert_eq!(typo[0].name, "Cheese, cheddar");
+		assert!(typo[0].relevance < exact[0].relevance);
+
+		assert_eq!(index.search("milk", Some(2)).len(), 2);
+		// Nothing typed yet lists everything, as before.
+		assert_eq!(index.search("", None).len(), foods.len());
 	}
 }
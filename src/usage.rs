@@ -0,0 +1,217 @@
+// How often, how recently and when each food gets logged, so search can put a user's usual foods first.
+// Kept up to date as meals change instead of being recounted for every keystroke.
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use hashbrown::HashMap;
+
+use crate::food::FoodID;
+use crate::meal::Meal;
+
+// A use this long ago counts half as much as one today.
+const HALF_LIFE_DAYS: f64 = 14.0;
+// Logs within this many hours of the current time count as "around this time of day".
+const TIME_OF_DAY_WINDOW_HOURS: u32 = 1;
+// How much history moves a food up.  A food logged daily for a month ends up about three times its text score.
+const USAGE_WEIGHT: f32 = 0.6;
+// How much more the history counts when it matches the time of day or meal name, for a food always eaten then.
+const TIME_OF_DAY_WEIGHT: f32 = 1.0;
+const MEAL_NAME_WEIGHT: f32 = 1.0;
+
+/// What a search is for, so foods usually logged at this time or in this kind of meal can come first.
+#[derive(Clone, Debug)]
+pub struct SearchContext {
+	pub now: DateTime<Utc>,
+	pub meal_name: Option<String>, // Boost foods usually logged under this meal name, e.g. "Breakfast".
+	pub boost_time_of_day: bool, // Boost foods usually logged around the hour of `now`.  Hours are in UTC, like Meal.time.
+}
+
+impl Default for SearchContext {
+	fn default() -> Self {
+		SearchContext {
+			now: Utc::now(),
+			meal_name: None,
+			boost_time_of_day: false,
+		}
+	}
+}
+
+// Uses decay continuously.  `decayed_uses` is the weighted count as of `as_of`, which only moves forward.
+struct FoodUsage {
+	decayed_uses: f64,
+	as_of: DateTime<Utc>,
+	uses: u32,
+	uses_by_hour: [u32; 24],
+	uses_by_meal_name: HashMap<String, u32>, // Lowercase.
+}
+
+#[derive(Default)]
+pub struct UsageStats {
+	foods: HashMap<FoodID, FoodUsage>,
+}
+
+impl UsageStats {
+	pub fn rebuild(&mut self, meals:&[Meal]) {
+		self.foods = HashMap::new();
+		for meal in meals {
+			for (food_id, _) in &meal.foods {
+				self.record(*food_id, meal.time, &meal.meal_name);
+			}
+		}
+	}
+
+	pub fn record(&mut self, food_id:FoodID, time:DateTime<Utc>, meal_name:&str) {
+		let usage = self.foods.entry(food_id).or_insert_with(|| FoodUsage {
+			decayed_uses: 0.0,
+			as_of: time,
+			uses: 0,
+			uses_by_hour: [0; 24],
+			uses_by_meal_name: HashMap::new(),
+		});
+		if time > usage.as_of {
+			usage.decayed_uses *= decay(time - usage.as_of);
+			usage.as_of = time;
+		}
+		usage.decayed_uses += decay(usage.as_of - time);
+		usage.uses += 1;
+		usage.uses_by_hour[time.hour() as usize] += 1;
+		*usage.uses_by_meal_name.entry(meal_name.to_lowercase()).or_insert(0) += 1;
+	}
+
+	/// Undo record, for a food taken out of a meal or a deleted meal.  Needs the time and meal name it was recorded with.
+	pub fn forget(&mut self, food_id:FoodID, time:DateTime<Utc>, meal_name:&str) {
+		let usage = match self.foods.get_mut(&food_id) {
+			Some(usage) if usage.uses > 1 => usage,
+			_ => { self.foods.remove(&food_id); return; },
+		};
+		usage.decayed_uses = (usage.decayed_uses - decay(usage.as_of - time)).max(0.0);
+		usage.uses -= 1;
+		let hour = &mut usage.uses_by_hour[time.hour() as usize];
+		*hour = hour.saturating_sub(1);
+		if let Some(count) = usage.uses_by_meal_name.get_mut(&meal_name.to_lowercase()) {
+			*count = count.saturating_sub(1);
+		}
+	}
+
+	/// How much to multiply a food's search score by.  One for foods never logged.
+	pub fn boost(&self, food_id:FoodID, context:&SearchContext) -> f32 {
+		let usage = match self.foods.get(&food_id) {
+			Some(usage) => usage,
+			None => { return 1.0; },
+		};
+		// Uses logged after `now` (say, a meal planned for tomorrow) count as if they were now.
+		let decayed_uses = if context.now > usage.as_of { usage.decayed_uses * decay(context.now - usage.as_of) } else { usage.decayed_uses };
+
+		let mut fit = 1.0;
+		if context.boost_time_of_day {
+			let hour = context.now.hour();
+			let nearby_uses: u32 = (0..24).filter(|h| hours_apart(*h, hour) <= TIME_OF_DAY_WINDOW_HOURS).map(|h| usage.uses_by_hour[h as usize]).sum();
+			fit += TIME_OF_DAY_WEIGHT * nearby_uses as f32 / usage.uses as f32;
+		}
+		if let Some(meal_name) = &context.meal_name {
+			let meal_uses = usage.uses_by_meal_name.get(&meal_name.to_lowercase()).copied().unwrap_or(0);
+			fit += MEAL_NAME_WEIGHT * meal_uses as f32 / usage.uses as f32;
+		}
+		1.0 + USAGE_WEIGHT * (decayed_uses as f32).ln_1p() * fit
+	}
+}
+
+fn decay(age:Duration) -> f64 {
+	let days = age.num_seconds() as f64 / 86_400.0;
+	0.5f64.powf(days / HALF_LIFE_DAYS)
+}
+
+// On a clock, so 23:00 and 00:00 are an hour apart.
+fn hours_apart(a:u32, b:u32) -> u32 {
+	let difference = a.abs_diff(b);
+	difference.min(24 - difference)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::*;
+	use chrono::{DateTime, Duration, TimeZone, Utc};
+
+	fn log(db: &mut FoodDB, food: FoodID, time: DateTime<Utc>, meal_name: &str) -> MealID {
+		let meal = db.new_meal();
+		db.meal_mut(meal).unwrap().time = time;
+		db.meal_mut(meal).unwrap().meal_name = meal_name.to_string();
+		db.add_food_to_meal(meal, food, FoodQuantity::Mass(100.0)).unwrap();
+		meal
+	}
+
+	fn names(results: Vec<FoodSearchResult>) -> Vec<String> {
+		results.into_iter().map(|r| r.name).collect()
+	}
+
+	#[test]
+	fn usual_foods_come_first() {
+		let mut db = FoodDB::new();
+		let mut add = |name: &str| { let food = db.new_food(); food.name = name.to_string(); food.id };
+		let whole = add("Milk, whole");
+		let skim = add("Milk, skim");
+		let millet = add("Millet, cooked");
+		let salsa = add("Mild salsa");
+		db.reindex();
+		let today = Utc.with_ymd_and_hms(2021, 3, 20, 8, 0, 0).unwrap();
+		let at = |hour: u32| SearchContext { now: today + Duration::hours(hour as i64 - 8), ..SearchContext::default() };
+
+		// Nothing logged, so it's down to the words.  "Mild" is the rarest one starting with "mil".
+		assert_eq!(names(db.search_foods_in_context("mil", None, &at(8))), vec!["Mild salsa", "Milk, skim", "Milk, whole", "Millet, cooked"]);
+		let millet_before = db.search_foods_in_context("mil", None, &at(8)).into_iter().find(|r| r.id == millet).unwrap().relevance;
+
+		// Skim milk with breakfast and whole milk with dinner, every day for the last ten days.  Millet every day for a
+		// month, a year ago.
+		for day in 1..=10 {
+			log(&mut db, skim, today - Duration::days(day), "Breakfast");
+			log(&mut db, whole, today - Duration::days(day) + Duration::hours(11), "Dinner");
+		}
+		for day in 0..30 {
+			log(&mut db, millet, today - Duration::days(365 + day), "Lunch");
+		}
+		let results = names(db.search_foods_in_context("mil", None, &at(8)));
+		assert_eq!(&results[..2], ["Milk, skim".to_string(), "Milk, whole".to_string()]);
+		assert_eq!(results[3], "Millet, cooked");
+
+		// A month of logs a year ago hardly counts any more.  Two yesterday do.
+		let relevance = |db: &FoodDB, food: FoodID| db.search_foods_in_context("mil", None, &at(8)).into_iter().find(|r| r.id == food).unwrap().relevance;
+		assert!((relevance(&db, millet) - millet_before).abs() < 1e-3);
+		let salsa_before = relevance(&db, salsa);
+		log(&mut db, salsa, today - Duration::days(1) + Duration::hours(4), "Lunch");
+		let salsa_meal = log(&mut db, salsa, today - Duration::days(1) + Duration::hours(4), "Lunch");
+		let salsa_logged = relevance(&db, salsa);
+		assert!(salsa_logged > salsa_before + 0.05);
+
+		// The time of day and the meal being logged pick between the two milks.
+		let evening = SearchContext { boost_time_of_day: true, ..at(19) };
+		assert_eq!(names(db.search_foods_in_context("mil", Some(1), &evening)), vec!["Milk, whole"]);
+		let morning = SearchContext { boost_time_of_day: true, ..at(8) };
+		assert_eq!(names(db.search_foods_in_context("mil", Some(1), &morning)), vec!["Milk, skim"]);
+		let dinner = SearchContext { meal_name: Some("dinner".to_string()), ..at(8) };
+		assert_eq!(names(db.search_foods_in_context("mil", Some(1), &dinner)), vec!["Milk, whole"]);
+
+		// Taking logs back is counted straight away, and agrees with a full rebuild.
+		db.delete_meal(salsa_meal).unwrap();
+		let one_log = relevance(&db, salsa);
+		assert!(one_log < salsa_logged && one_log > salsa_before);
+		db.reindex();
+		assert!((relevance(&db, salsa) - one_log).abs() < 1e-6);
+		let meal = db.meals.iter().find(|m| m.foods.iter().any(|(f, _)| *f == salsa)).unwrap().id;
+		db.remove_food_from_meal(meal, 0).unwrap();
+		assert!((relevance(&db, salsa) - salsa_before).abs() < 1e-6);
+
+		// Moving a meal after logging moves its usage too, so deleting it later takes back the right counts.
+		let skim_meal = log(&mut db, skim, today - Duration::hours(1), "Breakfast");
+		db.set_meal_time(skim_meal, today + Duration::hours(11)).unwrap();
+		db.rename_meal(skim_meal, "Dinner").unwrap();
+		let moved = relevance(&db, skim);
+		let moved_dinner = db.search_foods_in_context("mil", None, &dinner).into_iter().find(|r| r.id == skim).unwrap().relevance;
+		db.reindex();
+		assert!((relevance(&db, skim) - moved).abs() < 1e-6);
+		assert!((db.search_foods_in_context("mil", None, &dinner).into_iter().find(|r| r.id == skim).unwrap().relevance - moved_dinner).abs() < 1e-6);
+		db.delete_meal(skim_meal).unwrap();
+		let deleted = db.search_foods_in_context("mil", None, &evening).into_iter().find(|r| r.id == skim).unwrap().relevance;
+		db.reindex();
+		assert!((db.search_foods_in_context("mil", None, &evening).into_iter().find(|r| r.id == skim).unwrap().relevance - deleted).abs() < 1e-6);
+	}
+}